@@ -0,0 +1,97 @@
+use std::io;
+use std::path::PathBuf;
+
+/// How `--drop-caches` gets the next run to read from disk again
+pub enum CacheDrop {
+    /// Everything, through /proc/sys/vm/drop_caches (needs root)
+    System,
+    /// Just these files, through posix_fadvise(DONTNEED)
+    Files(Vec<PathBuf>),
+}
+
+#[cfg(target_os = "linux")]
+const DROP_CACHES: &str = "/proc/sys/vm/drop_caches";
+
+/// Files under `path`, or `path` itself when it isn't a directory. Symlinked
+/// directories inside it are skipped, they may well lead back up.
+#[cfg(target_os = "linux")]
+fn expand(path: &PathBuf, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        out.push(path.clone());
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        // Unlike `Path::is_dir`, this doesn't follow symlinks
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            expand(&entry.path(), out)?;
+        } else if !(kind.is_symlink() && entry.path().is_dir()) {
+            out.push(entry.path());
+        }
+    }
+    Ok(())
+}
+
+impl CacheDrop {
+    /// Uses the whole-system drop when we are allowed to, the file list
+    /// otherwise
+    #[cfg(target_os = "linux")]
+    pub fn new(files: &[PathBuf]) -> anyhow::Result<Self> {
+        let privileged = std::fs::OpenOptions::new().write(true).open(DROP_CACHES).is_ok();
+        if privileged {
+            eprintln!("Dropping the page cache before every run");
+            return Ok(CacheDrop::System);
+        }
+        if files.is_empty() {
+            anyhow::bail!(
+                "--drop-caches needs root to write {}; run as root or list the files to \
+                 evict with --cache-file",
+                DROP_CACHES
+            );
+        }
+        let mut expanded = Vec::new();
+        for path in files {
+            expand(path, &mut expanded)?;
+        }
+        eprintln!(
+            "Not allowed to drop the whole page cache, evicting {} file(s) before every run",
+            expanded.len()
+        );
+        Ok(CacheDrop::Files(expanded))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new(_files: &[PathBuf]) -> anyhow::Result<Self> {
+        anyhow::bail!("--drop-caches is only supported on Linux")
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn run(&self) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        // Dirty pages can't be dropped, so write them out first
+        unsafe { libc::sync() };
+        match self {
+            // 3 = page cache plus dentries and inodes
+            CacheDrop::System => std::fs::write(DROP_CACHES, "3"),
+            CacheDrop::Files(files) => {
+                for path in files {
+                    let file = std::fs::File::open(path)?;
+                    let ret = unsafe {
+                        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED)
+                    };
+                    if ret != 0 {
+                        return Err(io::Error::from_raw_os_error(ret));
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn run(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
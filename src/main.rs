@@ -1,17 +1,58 @@
 use clap::Parser;
-use std::process::Command;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::io::{self, Read, Write};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use anyhow::Result;
-use sysinfo::System; // 0.30+: no SystemExt/ProcessExt
+
+mod cache;
+mod procs;
+mod runner;
+mod stats;
+mod stopwatch;
+mod tree;
+mod trigger;
+mod until;
+use stats::{ConfidenceInterval, Summary};
 
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// How many times to run the command
     #[arg(short = 'n', long, default_value_t = 1)]
     runs: usize,
 
+    /// Keep running until the mean's CI half-width is within this much of it (e.g. 2%)
+    #[arg(long, value_parser = parse_precision, conflicts_with = "runs")]
+    target_precision: Option<f64>,
+
+    /// Run as many times as fit in this wall-clock budget per command (e.g. 60s)
+    #[arg(long, value_parser = parse_duration, conflicts_with_all = ["runs", "target_precision"])]
+    duration: Option<Duration>,
+
+    /// Fewest runs before --target-precision may stop
+    #[arg(long, default_value_t = 3, requires = "target_precision")]
+    min_runs: usize,
+
+    /// Most runs --target-precision may take
+    #[arg(long, default_value_t = 1000, requires = "target_precision")]
+    max_runs: usize,
+
+    /// Give up on --target-precision after this long (e.g. 90s, 10m)
+    #[arg(
+        long,
+        default_value = "10m",
+        value_parser = parse_duration,
+        requires = "target_precision"
+    )]
+    max_time: Duration,
+
+    /// Untimed runs before measuring (e.g. to warm caches)
+    #[arg(long, default_value_t = 0)]
+    warmup: usize,
+
     /// Emit results in JSON
     #[arg(long)]
     json: bool,
@@ -20,16 +61,419 @@ struct Args {
     #[arg(long)]
     wait: bool,
 
+    /// Where --wait and --stopwatch take their start / lap / stop marks from:
+    /// stdin, signal (SIGUSR1 / SIGUSR2), fifo:PATH or socket:PATH
+    #[arg(long, default_value = "stdin", value_parser = trigger::TriggerSpec::parse)]
+    trigger: trigger::TriggerSpec,
+
+    /// Time by hand instead of running a command: the trigger starts, marks
+    /// laps and stops
+    #[arg(long, conflicts_with_all = ["cmd", "commands", "wait"])]
+    stopwatch: bool,
+
+    /// Percentiles to report, comma separated (0-100)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_values_t = [50.0, 90.0, 95.0, 99.0],
+        value_parser = parse_percentile
+    )]
+    percentiles: Vec<f64>,
+
+    /// Confidence level for the bootstrap intervals
+    #[arg(long, default_value_t = 0.95, value_parser = parse_level)]
+    ci_level: f64,
+
+    /// Number of bootstrap resamples (0 disables the intervals)
+    #[arg(long, default_value_t = 10_000)]
+    bootstrap_resamples: usize,
+
+    /// Seed for the bootstrap resampling
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+
+    /// Warn when more than this fraction of runs are outliers
+    #[arg(long, default_value_t = 0.1)]
+    outlier_threshold: f64,
+
+    /// Also report statistics with outlier runs left out
+    #[arg(long)]
+    exclude_outliers: bool,
+
+    /// Earlier `--json` output to test these results against
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<std::path::PathBuf>,
+
+    /// Significance level for the Welch / Mann-Whitney tests
+    #[arg(long, default_value_t = 0.05)]
+    alpha: f64,
+
+    #[command(flatten)]
+    filter: ProcFilterArgs,
+
+    /// Window the process CPU usage is measured over (default: sysinfo's minimum)
+    #[arg(long, value_parser = parse_duration)]
+    sample_interval: Option<Duration>,
+
+    /// Sample CPU, memory, threads and I/O of the command's process tree this
+    /// often during each run (e.g. 100ms)
+    #[arg(long, value_parser = parse_duration)]
+    trace_interval: Option<Duration>,
+
+    /// Follow every process the command starts, even after it exits, and report
+    /// their combined CPU time, peak memory and count
+    #[arg(long)]
+    track_tree: bool,
+
+    /// Stop timing when a new process with this name appears (the command may
+    /// keep running, e.g. a launcher that starts the client)
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = procs::ProcFilter::parse,
+        group = "until"
+    )]
+    until_process: Option<procs::ProcFilter>,
+
+    /// Stop timing once a new process with this name has started and exited again
+    #[arg(
+        long,
+        value_name = "NAME",
+        value_parser = procs::ProcFilter::parse,
+        group = "until"
+    )]
+    until_process_exit: Option<procs::ProcFilter>,
+
+    /// Stop timing when the command prints a line matching this regex
+    #[arg(long, value_name = "REGEX", group = "until")]
+    ready_pattern: Option<regex::Regex>,
+
+    /// Stop timing when this file is created or modified
+    #[arg(long, value_name = "PATH", group = "until")]
+    ready_file: Option<std::path::PathBuf>,
+
+    /// Stop timing when this local TCP port accepts connections
+    #[arg(long, value_name = "PORT", group = "until")]
+    ready_port: Option<u16>,
+
+    /// What to do with the command once it is ready
+    #[arg(long, value_enum, default_value_t = runner::OnReady::Kill)]
+    on_ready: runner::OnReady,
+
+    /// Kill a run that takes longer than this (e.g. 30s). The command gets its
+    /// own process group so whatever it started is killed too, and reads
+    /// stdin from /dev/null since it can't use the terminal from there.
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+
+    /// How long a command we are killing gets after SIGTERM before SIGKILL
+    #[arg(long, default_value = "2s", value_parser = parse_duration)]
+    kill_grace: Duration,
+
+    /// Exit codes that count as success, comma separated
+    #[arg(long, value_delimiter = ',', default_values_t = [0], allow_negative_numbers = true)]
+    expect_exit_codes: Vec<i32>,
+
+    /// Stop at the first failed run instead of finishing the benchmark
+    #[arg(long, conflicts_with = "ignore_failure")]
+    fail_fast: bool,
+
+    /// Count failed runs like any other and exit with success anyway
+    #[arg(long)]
+    ignore_failure: bool,
+
+    /// Run each command line through this shell, so pipes, globs and builtins
+    /// work. Its startup time is measured, and a summary with it subtracted is
+    /// reported next to the raw times.
+    #[arg(long, value_enum, default_value_t = runner::Shell::None)]
+    shell: runner::Shell,
+
+    /// Run this before every run, warmups included, outside the timed window
+    #[arg(long, value_name = "COMMAND")]
+    prepare: Option<String>,
+
+    /// Run this after every run, outside the timed window
+    #[arg(long, value_name = "COMMAND")]
+    cleanup: Option<String>,
+
+    /// Run this once before each command's benchmark
+    #[arg(long, value_name = "COMMAND")]
+    setup: Option<String>,
+
+    /// Run this once after each command's benchmark
+    #[arg(long, value_name = "COMMAND")]
+    teardown: Option<String>,
+
+    /// Start every timed run with a cold page cache (Linux). Drops the whole
+    /// cache when run as root, otherwise evicts the --cache-file paths.
+    #[arg(long)]
+    drop_caches: bool,
+
+    /// File or directory to evict from the page cache when not root (repeatable)
+    #[arg(long, value_name = "PATH", requires = "drop_caches")]
+    cache_file: Vec<std::path::PathBuf>,
+
+    /// Skip timing a no-op command to estimate our own spawn / wait overhead
+    #[arg(long)]
+    no_calibrate: bool,
+
+    /// Another command to benchmark as a single quoted string (repeatable)
+    #[arg(long = "cmd", value_name = "COMMAND")]
+    commands: Vec<String>,
+
     /// Command to run (everything after --)
-    #[arg(trailing_var_arg = true, required = true)]
+    #[arg(trailing_var_arg = true, required_unless_present_any = ["commands", "stopwatch"])]
     cmd: Vec<String>,
 }
 
+#[derive(clap::Args, Debug)]
+struct ProcFilterArgs {
+    /// Only list processes whose name, path or command line contains this
+    /// (repeatable, `re:` prefix for a regex)
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        default_values = ["riot", "league"],
+        value_parser = procs::ProcFilter::parse
+    )]
+    proc_filter: Vec<procs::ProcFilter>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Commands {
+    /// Live view of the matching processes, refreshed until Ctrl-C
+    Monitor {
+        /// How often to refresh (e.g. 500ms, 2s)
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        interval: Duration,
+
+        /// Column to sort by
+        #[arg(long, value_enum, default_value_t = procs::SortBy::Cpu)]
+        sort: procs::SortBy,
+
+        /// Only show this many processes
+        #[arg(long)]
+        top: Option<usize>,
+
+        #[command(flatten)]
+        filter: ProcFilterArgs,
+    },
+}
+
 #[derive(Serialize)]
 struct RunResult {
+    command: String,
     exit_code: Option<i32>,
+    warmup_times: Vec<f64>,
+    /// Number of measured runs completed
+    runs: usize,
+    /// The --duration budget in seconds, if one was used
+    budget: Option<f64>,
     times: Vec<f64>,
+    /// How each run in `times` ended
+    statuses: Vec<runner::RunStatus>,
+    /// Per-run exit code, None when killed by a signal or left running
+    exit_codes: Vec<Option<i32>>,
+    /// Per-run signal that ended the command (Unix only)
+    signals: Vec<Option<i32>>,
+    /// Runs that timed out, were killed or exited with an unexpected code
+    failed: usize,
+    #[serde(flatten)]
+    stats: Summary,
+    /// Relative CI half-width reached with --target-precision
+    precision: Option<f64>,
+    mean_ci: Option<ConfidenceInterval>,
+    median_ci: Option<ConfidenceInterval>,
+    /// Indices into `times`
+    outliers: Vec<usize>,
+    /// Summary without the outliers, with `--exclude-outliers`
+    trimmed: Option<Summary>,
+    /// Summary with the --shell startup subtracted; `times` stay as measured
+    corrected: Option<Summary>,
+    /// Per-run user / system CPU seconds of the child, lined up with `times`;
+    /// None where the platform didn't report them
+    user_times: Vec<Option<f64>>,
+    system_times: Vec<Option<f64>>,
+    user: Option<Summary>,
+    system: Option<Summary>,
+    /// Per-run peak RSS of the child in KiB, lined up with `times`. Never below
+    /// the harness's own RSS (see `overhead.rss_floor_kib`), so only an upper
+    /// bound for small commands.
+    max_rss_kib: Vec<Option<u64>>,
+    max_rss: Option<Summary>,
+    /// One resource timeline per run, with --trace-interval
+    timelines: Vec<Vec<tree::TraceSample>>,
+    /// Whole process tree totals per run, with --track-tree
+    tree: Vec<tree::TreeUsage>,
+}
+
+#[derive(Serialize)]
+struct Relative {
+    command: String,
+    mean: f64,
+    stddev: f64,
+    /// How many times slower than the fastest command
+    ratio: f64,
+    ratio_stddev: f64,
+}
+
+#[derive(Serialize)]
+struct Significance {
+    command: String,
+    against: String,
+    /// `against` is the --baseline entry rather than this run's fastest command
+    baseline: bool,
+    welch: Option<stats::WelchTest>,
+    mann_whitney: Option<stats::MannWhitney>,
+    cohens_d: f64,
+    rank_biserial: Option<f64>,
+    /// Both tests reject "same distribution" at `alpha`
+    significant: bool,
+}
+
+/// What spawning and waiting for a command costs on its own
+#[derive(Serialize, Clone, Copy)]
+struct Overhead {
     mean: f64,
+    stddev: f64,
+    /// Peak RSS the kernel reports for the no-op, i.e. the lowest `max_rss_kib`
+    /// any command can get
+    rss_floor_kib: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Report {
+    results: Vec<RunResult>,
+    /// Only filled in when more than one command was benchmarked
+    comparison: Vec<Relative>,
+    significance: Vec<Significance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stopwatch: Option<stopwatch::Stopwatch>,
+    /// Seconds of shell startup subtracted in each `corrected` summary, with --shell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shell_correction: Option<f64>,
+    /// Harness overhead measured with a no-op command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    overhead: Option<Overhead>,
+}
+
+// Just the parts of an earlier report that the tests need
+#[derive(Deserialize)]
+struct BaselineEntry {
+    command: String,
+    times: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct Baseline {
+    results: Vec<BaselineEntry>,
+}
+
+fn load_baseline(path: &std::path::Path) -> Result<Baseline> {
+    let text = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+fn parse_percentile(s: &str) -> Result<f64, String> {
+    let p: f64 = s.trim().parse().map_err(|_| format!("`{s}` is not a number"))?;
+    if !(0.0..=100.0).contains(&p) {
+        return Err(format!("percentile {p} is outside 0-100"));
+    }
+    Ok(p)
+}
+
+fn parse_level(s: &str) -> Result<f64, String> {
+    let level: f64 = s.trim().parse().map_err(|_| format!("`{s}` is not a number"))?;
+    if !(level > 0.0 && level < 1.0) {
+        return Err(format!("confidence level {level} must be between 0 and 1"));
+    }
+    Ok(level)
+}
+
+/// Accepts `2%` or a plain fraction like `0.02`
+fn parse_precision(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let value = match s.strip_suffix('%') {
+        Some(pct) => pct.trim().parse::<f64>().map(|p| p / 100.0),
+        None => s.parse::<f64>(),
+    }
+    .map_err(|_| format!("`{s}` is not a precision like 2% or 0.02"))?;
+    if value.is_nan() || value <= 0.0 {
+        return Err("precision must be above zero".to_string());
+    }
+    Ok(value)
+}
+
+/// Accepts `500ms`, `30s`, `10m`, `1h`, or bare seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.trim().parse().map_err(|_| format!("`{s}` is not a duration"))?;
+    let secs = match unit {
+        "ms" => num / 1000.0,
+        "" | "s" => num,
+        "m" => num * 60.0,
+        "h" => num * 3600.0,
+        _ => return Err(format!("unknown unit `{unit}` in `{s}` (use ms, s, m or h)")),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("`{s}`: {e}"))
+}
+
+/// Splits a `--cmd` string into program and arguments, honouring quotes
+fn split_command(s: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut cur = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    cur.push(next);
+                }
+            }
+            (Some(_), c) => cur.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+                continue;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut cur));
+                    in_word = false;
+                }
+                continue;
+            }
+            (None, c) => cur.push(c),
+        }
+        in_word = true;
+    }
+
+    if quote.is_some() {
+        anyhow::bail!("unterminated quote in command `{s}`");
+    }
+    if in_word {
+        words.push(cur);
+    }
+    if words.is_empty() {
+        anyhow::bail!("empty command");
+    }
+    Ok(words)
+}
+
+fn print_summary(s: &Summary) {
+    println!("Mean: {:.3} sec", s.mean);
+    println!("Std dev: {:.3} sec", s.stddev);
+    println!("Median: {:.3} sec", s.median);
+    println!("Min: {:.3} sec  Max: {:.3} sec", s.min, s.max);
+    for p in &s.percentiles {
+        println!("p{}: {:.3} sec", p.percentile, p.value);
+    }
 }
 
 fn wait_for_enter_if_requested(wait: bool) -> Result<()> {
@@ -41,68 +485,827 @@ fn wait_for_enter_if_requested(wait: bool) -> Result<()> {
     Ok(())
 }
 
-fn spawn_cross_platform(cmd: &[String]) -> std::io::Result<std::process::ExitStatus> {
-    #[cfg(target_os = "windows")]
-    {
-        // Builtins like `echo` require running through cmd.exe
-        let mut c = Command::new("cmd");
-        c.arg("/C").arg(&cmd[0]).args(&cmd[1..]).status()
+fn run_options(args: &Args) -> runner::RunOptions {
+    runner::RunOptions {
+        trace_interval: args.trace_interval,
+        track_tree: args.track_tree,
+        until: until_condition(args),
+        on_ready: args.on_ready,
+        timeout: args.timeout,
+        kill_grace: args.kill_grace,
+        // Anything the command prints would end up in the middle of the JSON
+        stdout_to_stderr: args.json,
+    }
+}
+
+// Clap's "until" group makes these mutually exclusive
+fn until_condition(args: &Args) -> Option<until::Until> {
+    use until::Until;
+    if let Some(f) = &args.until_process {
+        Some(Until::ProcessAppears(f.clone()))
+    } else if let Some(f) = &args.until_process_exit {
+        Some(Until::ProcessExits(f.clone()))
+    } else if let Some(re) = &args.ready_pattern {
+        Some(Until::Stdout(re.clone()))
+    } else if let Some(path) = &args.ready_file {
+        Some(Until::File(path.clone()))
+    } else {
+        args.ready_port.map(Until::Port)
+    }
+}
+
+fn wait_for_trigger(spec: &trigger::TriggerSpec) -> Result<()> {
+    let mut t = trigger::Trigger::open(spec)?;
+    eprintln!("Waiting for the start trigger: {}", spec.describe());
+    while t.next(false)? != trigger::Mark::Start {}
+    Ok(())
+}
+
+/// Whether a run counts against the benchmark, given `--expect-exit-codes`
+fn run_failed(m: &runner::Measurement, expected: &[i32]) -> bool {
+    match m.outcome {
+        runner::RunStatus::NonZero { code } => !expected.contains(&code),
+        // Also covers runs stopped by an `until` condition, however they ended
+        runner::RunStatus::Ok => m.exit_code() == Some(0) && !expected.contains(&0),
+        runner::RunStatus::Signaled { .. } | runner::RunStatus::TimedOut => true,
+    }
+}
+
+/// Runs of the empty / no-op commands used for calibration
+const CALIBRATION_RUNS: usize = 20;
+
+/// Warn when a command is faster than this many times the harness overhead
+const OVERHEAD_WARN_FACTOR: f64 = 5.0;
+
+/// A peak RSS within this factor of the no-op's is treated as the floor
+const RSS_FLOOR_SLACK: f64 = 1.05;
+
+/// Runs a command that does next to nothing
+fn time_noop(argv: &[String]) -> Result<Vec<runner::Measurement>> {
+    let opts = runner::RunOptions::default();
+    // The first spawn pays for loading the program from disk
+    runner::spawn_cross_platform(argv, &opts)?;
+    let mut runs = Vec::with_capacity(CALIBRATION_RUNS);
+    for _ in 0..CALIBRATION_RUNS {
+        runs.push(runner::spawn_cross_platform(argv, &opts)?);
+    }
+    Ok(runs)
+}
+
+/// Spawn + wait cost of a program that exits straight away
+fn calibrate_overhead() -> Result<Overhead> {
+    // build_command already wraps this in `cmd /C` on Windows
+    let noop = if cfg!(windows) { "exit" } else { "true" };
+    let runs = time_noop(&[noop.to_string()])?;
+    let times: Vec<f64> = runs.iter().map(|m| m.elapsed).collect();
+    let overhead = Overhead {
+        mean: stats::mean(&times),
+        stddev: stats::stddev(&times),
+        rss_floor_kib: runs.iter().filter_map(|m| m.max_rss_kib).max(),
+    };
+    eprintln!(
+        "Harness overhead: {:.3} ms ± {:.3} ms per run",
+        overhead.mean * 1000.0,
+        overhead.stddev * 1000.0
+    );
+    Ok(overhead)
+}
+
+/// Mean wall time of the shell running nothing at all
+fn calibrate_shell(shell: runner::Shell) -> Result<Option<f64>> {
+    let Some(empty) = shell.wrap("") else {
+        return Ok(None);
+    };
+    let times: Vec<f64> = time_noop(&empty)?.iter().map(|m| m.elapsed).collect();
+    let correction = stats::mean(&times);
+    eprintln!(
+        "Shell startup: {:.3} ms ± {:.3} ms, subtracted in the corrected summary",
+        correction * 1000.0,
+        stats::stddev(&times) * 1000.0
+    );
+    Ok(Some(correction))
+}
+
+/// Runs one of the --prepare / --cleanup / --setup / --teardown commands,
+/// through --shell like the benchmarked ones. Any failure is an error.
+fn run_hook(flag: &str, line: Option<&str>, args: &Args) -> Result<()> {
+    let Some(line) = line else {
+        return Ok(());
+    };
+    let argv = match args.shell.wrap(line) {
+        Some(argv) => argv,
+        None => split_command(line)?,
+    };
+    let status = runner::run_untimed(&argv, args.json)?;
+    if !status.success() {
+        anyhow::bail!("--{} `{}` failed ({})", flag, line, status);
     }
-    #[cfg(not(target_os = "windows"))]
+    Ok(())
+}
+
+fn bench_command(
+    label: String,
+    cmd: &[String],
+    args: &Args,
+    correction: Option<f64>,
+    caches: Option<&cache::CacheDrop>,
+) -> Result<RunResult> {
+    run_hook("setup", args.setup.as_deref(), args)?;
+    let result = measure(label, cmd, args, correction, caches);
+    // Also after --fail-fast or a failed hook, so nothing is left behind
+    let teardown = run_hook("teardown", args.teardown.as_deref(), args);
+    let result = result?;
+    teardown?;
+    Ok(result)
+}
+
+/// The warmups and measured runs of one command, between setup and teardown
+fn measure(
+    label: String,
+    cmd: &[String],
+    args: &Args,
+    correction: Option<f64>,
+    caches: Option<&cache::CacheDrop>,
+) -> Result<RunResult> {
+    let opts = run_options(args);
+    let hook = |flag, line: &Option<String>| run_hook(flag, line.as_deref(), args);
+
+    // Warmups go through the same path but are kept out of the stats
+    let mut warmup_times = Vec::with_capacity(args.warmup);
+    for i in 0..args.warmup {
+        hook("prepare", &args.prepare)?;
+        // Clean up even after an interrupted run
+        let m = runner::spawn_cross_platform(cmd, &opts);
+        let cleaned = hook("cleanup", &args.cleanup);
+        let m = m?;
+        cleaned?;
+        eprintln!("Warmup {} ended ({}) after {:.3} seconds", i + 1, m.outcome, m.elapsed);
+        if args.fail_fast && run_failed(&m, &args.expect_exit_codes) {
+            anyhow::bail!("warmup {} of `{}` failed ({})", i + 1, label, m.outcome);
+        }
+        warmup_times.push(m.elapsed);
+    }
+
+    let mut times = Vec::with_capacity(args.runs);
+    let mut statuses = Vec::with_capacity(args.runs);
+    let mut exit_codes = Vec::with_capacity(args.runs);
+    let mut signals = Vec::with_capacity(args.runs);
+    let mut failed = 0;
+    let mut user_times = Vec::with_capacity(args.runs);
+    let mut system_times = Vec::with_capacity(args.runs);
+    let mut max_rss_kib = Vec::with_capacity(args.runs);
+    let mut timelines = Vec::new();
+    let mut tree_usage = Vec::new();
+    let mut last_code: Option<i32> = None;
+    let mut precision = None;
+    let measuring = Instant::now();
+
+    loop {
+        if let Some(target) = args.target_precision {
+            precision = stats::relative_half_width(&times, args.ci_level);
+            let out_of_time = measuring.elapsed() >= args.max_time;
+            let out_of_runs = times.len() >= args.max_runs;
+            let precise = precision.is_some_and(|p| p <= target);
+            // A precision relative to a mean of ~0 never converges
+            if times.len() >= args.min_runs.max(2) && stats::mean(&times).abs() < 1e-9 {
+                eprintln!(
+                    "Stopped after {} runs: the mean time is about 0, so there is no \
+                     relative precision to reach",
+                    times.len()
+                );
+                break;
+            }
+            if (times.len() >= args.min_runs && precise)
+                || out_of_runs
+                || (out_of_time && !times.is_empty())
+            {
+                if !precise {
+                    eprintln!(
+                        "Stopped before reaching {:.1}% precision after {} runs",
+                        target * 100.0,
+                        times.len()
+                    );
+                }
+                break;
+            }
+        } else if let Some(budget) = args.duration {
+            // Don't start a run that would likely overshoot the budget
+            let spent = measuring.elapsed().as_secs_f64();
+            if !times.is_empty() && spent + stats::mean(&times) > budget.as_secs_f64() {
+                break;
+            }
+        } else if times.len() >= args.runs {
+            break;
+        }
+
+        hook("prepare", &args.prepare)?;
+        if let Some(c) = caches {
+            c.run()?;
+        }
+        // Clean up even after an interrupted run
+        let m = runner::spawn_cross_platform(cmd, &opts);
+        let cleaned = hook("cleanup", &args.cleanup);
+        let m = m?;
+        cleaned?;
+
+        match (m.outcome, m.status) {
+            (runner::RunStatus::TimedOut | runner::RunStatus::Signaled { .. }, _) => {
+                eprintln!("Command {}", m.outcome)
+            }
+            (_, Some(status)) => eprintln!("Command exited with: {:?}", status.code()),
+            (_, None) => eprintln!("Command left running"),
+        }
+        eprintln!("Elapsed time: {:.3} seconds", m.elapsed);
+
+        if run_failed(&m, &args.expect_exit_codes) {
+            failed += 1;
+            if args.fail_fast {
+                anyhow::bail!("run {} of `{}` failed ({})", times.len() + 1, label, m.outcome);
+            }
+        }
+        last_code = m.exit_code();
+        times.push(m.elapsed);
+        statuses.push(m.outcome);
+        exit_codes.push(m.exit_code());
+        signals.push(m.signal());
+        user_times.push(m.user_time);
+        system_times.push(m.system_time);
+        max_rss_kib.push(m.max_rss_kib);
+        if args.trace_interval.is_some() {
+            timelines.push(m.timeline);
+        }
+        if let Some(t) = m.tree {
+            if t.still_running > 0 {
+                eprintln!(
+                    "{} descendant(s) still running after the command exited",
+                    t.still_running
+                );
+            }
+            tree_usage.push(t);
+        }
+    }
+
+    let timeouts = statuses.iter().filter(|s| **s == runner::RunStatus::TimedOut).count();
+    if timeouts > 0 {
+        eprintln!(
+            "Warning: {} of {} runs of `{}` timed out; their times are the timeout itself",
+            timeouts,
+            times.len(),
+            label
+        );
+    }
+
+    if failed > 0 {
+        eprintln!("Warning: {} of {} runs of `{}` failed", failed, times.len(), label);
+    }
+
+    let summary = stats::summarize(&times, &args.percentiles);
+    // None when the platform can't report rusage
+    let rusage_summary = |xs: &[Option<f64>]| {
+        let xs: Vec<f64> = xs.iter().flatten().copied().collect();
+        (!xs.is_empty()).then(|| stats::summarize(&xs, &args.percentiles))
+    };
+    let user = rusage_summary(&user_times);
+    let system = rusage_summary(&system_times);
+    let rss: Vec<Option<f64>> = max_rss_kib.iter().map(|k| k.map(|k| k as f64)).collect();
+    let max_rss = rusage_summary(&rss);
+    let outliers = stats::outliers(&times);
+    let outlier_share = outliers.len() as f64 / times.len().max(1) as f64;
+    if !outliers.is_empty() && outlier_share > args.outlier_threshold {
+        eprintln!(
+            "Warning: {} of {} runs of `{}` are outliers. The system may have been busy, \
+             or caching affected the first runs (try --warmup).",
+            outliers.len(),
+            times.len(),
+            label
+        );
+    }
+    let trimmed = args.exclude_outliers.then(|| {
+        let kept: Vec<f64> = times
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !outliers.contains(i))
+            .map(|(_, &t)| t)
+            .collect();
+        stats::summarize(&kept, &args.percentiles)
+    });
+    // Shifting every run by the same amount, so some may go below zero; the
+    // mean is what the correction is an estimate for
+    let corrected = correction.map(|c| {
+        let shifted: Vec<f64> = times.iter().map(|t| t - c).collect();
+        stats::summarize(&shifted, &args.percentiles)
+    });
+
+    let ci = |statistic| {
+        stats::bootstrap_ci(&times, statistic, args.ci_level, args.bootstrap_resamples, args.seed)
+    };
+    let mean_ci = ci(stats::mean);
+    let median_ci = ci(stats::median);
+
+    Ok(RunResult {
+        command: label,
+        exit_code: last_code,
+        warmup_times,
+        runs: times.len(),
+        budget: args.duration.map(|d| d.as_secs_f64()),
+        times,
+        statuses,
+        exit_codes,
+        signals,
+        failed,
+        stats: summary,
+        precision,
+        mean_ci,
+        median_ci,
+        outliers,
+        trimmed,
+        corrected,
+        user_times,
+        system_times,
+        user,
+        system,
+        max_rss_kib,
+        max_rss,
+        timelines,
+        tree: tree_usage,
+    })
+}
+
+/// Every command relative to the fastest one, fastest first
+fn compare(results: &[RunResult]) -> Vec<Relative> {
+    if results.len() < 2 {
+        return Vec::new();
+    }
+    // Ratios without the shell startup every command pays alike
+    fn summary(r: &RunResult) -> &Summary {
+        r.corrected.as_ref().unwrap_or(&r.stats)
+    }
+    let mut order: Vec<&RunResult> = results.iter().collect();
+    order.sort_by(|a, b| summary(a).mean.total_cmp(&summary(b).mean));
+    let fastest = summary(order[0]);
+
+    order
+        .iter()
+        .map(|r| {
+            let s = summary(r);
+            let (ratio, ratio_stddev) =
+                stats::ratio(s.mean, s.stddev, fastest.mean, fastest.stddev);
+            Relative {
+                command: r.command.clone(),
+                mean: s.mean,
+                stddev: s.stddev,
+                ratio,
+                ratio_stddev,
+            }
+        })
+        .collect()
+}
+
+fn significance(
+    command: &str,
+    times: &[f64],
+    against: &str,
+    baseline: bool,
+    other: &[f64],
+    alpha: f64,
+) -> Significance {
+    let welch = stats::welch_t_test(times, other);
+    let mann_whitney = stats::mann_whitney(times, other);
+    let significant = matches!(
+        (&welch, &mann_whitney),
+        (Some(w), Some(m)) if w.p_value < alpha && m.p_value < alpha
+    );
+    Significance {
+        command: command.to_string(),
+        against: against.to_string(),
+        baseline,
+        cohens_d: stats::cohens_d(times, other),
+        rank_biserial: mann_whitney
+            .as_ref()
+            .map(|m| stats::rank_biserial(m.u, times.len(), other.len())),
+        welch,
+        mann_whitney,
+        significant,
+    }
+}
+
+/// Tests every command against the fastest one and against the baseline
+fn test_significance(
+    results: &[RunResult],
+    baseline: Option<&Baseline>,
+    alpha: f64,
+) -> Vec<Significance> {
+    let mut out = Vec::new();
+
+    if let Some(fastest) = results
+        .iter()
+        .min_by(|a, b| a.stats.mean.total_cmp(&b.stats.mean))
+        .filter(|_| results.len() > 1)
     {
-        Command::new(&cmd[0]).args(&cmd[1..]).status()
+        for r in results.iter().filter(|r| !std::ptr::eq(*r, fastest)) {
+            out.push(significance(
+                &r.command,
+                &r.times,
+                &fastest.command,
+                false,
+                &fastest.times,
+                alpha,
+            ));
+        }
+    }
+
+    if let Some(base) = baseline {
+        for r in results {
+            // Match by command, or take a single-entry baseline as-is
+            let entry = base
+                .results
+                .iter()
+                .find(|b| b.command == r.command)
+                .or(if base.results.len() == 1 { base.results.first() } else { None });
+            if let Some(b) = entry {
+                out.push(significance(&r.command, &r.times, &b.command, true, &b.times, alpha));
+            } else {
+                eprintln!("No baseline entry for `{}`", r.command);
+            }
+        }
+    }
+
+    out
+}
+
+fn print_significance(rows: &[Significance], alpha: f64) {
+    println!("\nSignificance (alpha = {}):", alpha);
+    for s in rows {
+        let kind = if s.baseline { "baseline " } else { "" };
+        println!("  `{}` vs {}`{}`", s.command, kind, s.against);
+        match &s.welch {
+            Some(w) => println!(
+                "    Welch t = {:.3}, df = {:.1}, p = {:.4}",
+                w.t, w.df, w.p_value
+            ),
+            None => println!("    Welch t-test: not enough runs or no variance"),
+        }
+        match &s.mann_whitney {
+            Some(m) => println!("    Mann-Whitney U = {:.1}, p = {:.4}", m.u, m.p_value),
+            None => println!("    Mann-Whitney U: not enough runs"),
+        }
+        println!("    Cohen's d = {:.3}", s.cohens_d);
+        if let Some(r) = s.rank_biserial {
+            println!("    Rank-biserial r = {:.3}", r);
+        }
+        if s.significant {
+            println!("    -> statistically significant difference");
+        } else {
+            println!("    -> no significant difference (could be noise)");
+        }
+    }
+}
+
+fn print_stopwatch(sw: &stopwatch::Stopwatch) {
+    let width = sw.laps.iter().map(|l| l.name.len()).max().unwrap_or(0);
+    println!("Stopwatch:");
+    for lap in &sw.laps {
+        println!(
+            "  {:<width$}  {:>9.3} s  (+{:.3} s)",
+            lap.name,
+            lap.at,
+            lap.split,
+            width = width
+        );
+    }
+    println!("Total: {:.3} s", sw.total);
+}
+
+fn print_result(r: &RunResult) {
+    println!("Exit code: {:?}", r.exit_code);
+    if !r.warmup_times.is_empty() {
+        println!("Warmup times: {:?}", r.warmup_times);
+    }
+    match r.budget {
+        Some(b) => println!("Runs: {} (completed within a {}s budget)", r.runs, b),
+        None => println!("Runs: {}", r.runs),
+    }
+    if let Some(p) = r.precision {
+        println!("Precision: ±{:.2}% of the mean", p * 100.0);
+    }
+    println!("Times: {:?}", r.times);
+    if r.statuses.iter().any(|s| *s != runner::RunStatus::Ok) {
+        let runs: Vec<String> = r
+            .statuses
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s != runner::RunStatus::Ok)
+            .map(|(i, s)| format!("#{} {}", i + 1, s))
+            .collect();
+        println!("Not ok: {}", runs.join(", "));
+    }
+    if r.failed > 0 {
+        println!("Failed: {} of {} runs", r.failed, r.runs);
+    }
+    print_summary(&r.stats);
+    for (name, ci) in [("Mean", &r.mean_ci), ("Median", &r.median_ci)] {
+        if let Some(ci) = ci {
+            println!(
+                "{} {}% CI: [{:.3}, {:.3}] sec",
+                name,
+                ci.level * 100.0,
+                ci.lower,
+                ci.upper
+            );
+        }
+    }
+    if !r.outliers.is_empty() {
+        let runs: Vec<String> = r
+            .outliers
+            .iter()
+            .map(|&i| format!("#{} ({:.3} s)", i + 1, r.times[i]))
+            .collect();
+        println!("Outliers: {}", runs.join(", "));
+    }
+    if let Some(t) = &r.trimmed {
+        println!("Without outliers:");
+        print_summary(t);
+    }
+    if let Some(c) = &r.corrected {
+        println!("Without the shell startup:");
+        print_summary(c);
+    }
+    if let (Some(u), Some(s)) = (&r.user, &r.system) {
+        println!(
+            "CPU time: user {:.3} sec, system {:.3} sec (mean), {:.0}% of wall time",
+            u.mean,
+            s.mean,
+            (u.mean + s.mean) / r.stats.mean * 100.0
+        );
+    }
+    if let Some(m) = &r.max_rss {
+        println!(
+            "Peak memory: mean {:.0} KiB, min {:.0} KiB, max {:.0} KiB",
+            m.mean, m.min, m.max
+        );
+    }
+    if !r.tree.is_empty() {
+        let n = r.tree.len() as f64;
+        let procs = r.tree.iter().map(|t| t.processes as f64).sum::<f64>() / n;
+        let rss = r.tree.iter().map(|t| t.peak_rss_kib).max().unwrap_or(0);
+        print!("Process tree: {:.1} processes per run, peak combined RSS {} KiB", procs, rss);
+        let cpu: Option<Vec<f64>> = r.tree.iter().map(|t| t.cpu_time).collect();
+        match cpu {
+            Some(cpu) => println!(", CPU time {:.3} sec per run", stats::mean(&cpu)),
+            None => println!(),
+        }
+    }
+    if !r.timelines.is_empty() {
+        let samples = r.timelines.iter().flatten();
+        let peak_cpu = samples.clone().map(|s| s.cpu).fold(0.0, f32::max);
+        let peak_rss = samples.clone().map(|s| s.rss_kib).max().unwrap_or(0);
+        let peak_procs = samples.map(|s| s.processes).max().unwrap_or(0);
+        println!(
+            "Traced: peak CPU {:.1}%, peak RSS {} KiB, up to {} processes (timelines in --json)",
+            peak_cpu, peak_rss, peak_procs
+        );
+    }
+}
+
+fn print_comparison(rows: &[Relative]) {
+    let width = rows.iter().map(|r| r.command.len()).max().unwrap_or(0);
+    println!("\nComparison (fastest first):");
+    for (i, r) in rows.iter().enumerate() {
+        let relative = if i == 0 {
+            "fastest".to_string()
+        } else {
+            format!("{:.2} ± {:.2} times slower", r.ratio, r.ratio_stddev)
+        };
+        println!(
+            "  {:<width$}  {:>9.3} s ± {:<7.3}  {}",
+            r.command,
+            r.mean,
+            r.stddev,
+            relative,
+            width = width
+        );
     }
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    // Only wait if the flag is provided
-    wait_for_enter_if_requested(args.wait)?;
+    if let Some(Commands::Monitor {
+        interval,
+        sort,
+        top,
+        filter,
+    }) = &args.command
+    {
+        return procs::monitor(&filter.proc_filter, *interval, *sort, *top);
+    }
 
-    let mut times = Vec::with_capacity(args.runs);
-    let mut last_code: Option<i32> = None;
+    // With --shell each command line goes to the shell as-is
+    let mut commands = Vec::new();
+    if !args.cmd.is_empty() {
+        let line = args.cmd.join(" ");
+        let argv = args.shell.wrap(&line).unwrap_or_else(|| args.cmd.clone());
+        commands.push((line, argv));
+    }
+    for c in &args.commands {
+        let argv = match args.shell.wrap(c) {
+            Some(argv) => argv,
+            None => split_command(c)?,
+        };
+        commands.push((c.clone(), argv));
+    }
 
-    for _ in 0..args.runs {
-        let start = Instant::now();
-        let status = spawn_cross_platform(&args.cmd)?;
-        let elapsed = start.elapsed().as_secs_f64();
+    // Every kept command would still be running (and holding its port, or
+    // printing into our output) when the next run starts
+    let repeated = args.runs > 1
+        || args.warmup > 0
+        || args.target_precision.is_some()
+        || args.duration.is_some()
+        || commands.len() > 1;
+    let probe = until_condition(&args).is_some_and(|u| u.is_probe());
+    if probe && args.on_ready == runner::OnReady::Keep && repeated {
+        anyhow::bail!("--on-ready keep with a readiness probe only works for a single run");
+    }
 
-        println!("Command exited with: {:?}", status.code());
-        println!("Elapsed time: {:.3} seconds", elapsed);
+    let baseline = args.baseline.as_deref().map(load_baseline).transpose()?;
 
-        last_code = status.code();
-        times.push(elapsed);
+    // Only wait if the flag is provided
+    match &args.trigger {
+        trigger::TriggerSpec::Stdin => wait_for_enter_if_requested(args.wait)?,
+        spec if args.wait => wait_for_trigger(spec)?,
+        _ => {}
     }
 
-    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let stopwatch = if args.stopwatch {
+        let mut t = trigger::Trigger::open(&args.trigger)?;
+        Some(stopwatch::run(&mut t, &args.trigger.describe())?)
+    } else {
+        None
+    };
+
+    let caches = if args.drop_caches {
+        Some(cache::CacheDrop::new(&args.cache_file)?)
+    } else {
+        None
+    };
+    let overhead = if commands.is_empty() || args.no_calibrate {
+        None
+    } else {
+        Some(calibrate_overhead()?)
+    };
+    let shell_correction = if commands.is_empty() {
+        None
+    } else {
+        calibrate_shell(args.shell)?
+    };
+
+    let multiple = commands.len() > 1;
+    let mut results = Vec::with_capacity(commands.len());
+    for (label, cmd) in commands {
+        if multiple {
+            eprintln!("Benchmarking `{}`", label);
+        }
+        let result = bench_command(label, &cmd, &args, shell_correction, caches.as_ref());
+        // Teardown has run by now, so an interrupted run can finish us off
+        if result.is_err() {
+            runner::reraise_interrupt();
+        }
+        results.push(result?);
+    }
+    if let Some(o) = overhead {
+        for r in results.iter().filter(|r| r.stats.mean < OVERHEAD_WARN_FACTOR * o.mean) {
+            eprintln!(
+                "Warning: `{}` takes {:.3} ms, within {}x of the {:.3} ms it costs us to \
+                 start and wait for a process. Expect that overhead to skew the numbers.",
+                r.command,
+                r.stats.mean * 1000.0,
+                OVERHEAD_WARN_FACTOR,
+                o.mean * 1000.0
+            );
+        }
+        // Allow a little for the harness growing since calibration
+        let floor = o.rss_floor_kib.map(|k| k as f64 * RSS_FLOOR_SLACK);
+        for r in &results {
+            if let (Some(m), Some(floor)) = (&r.max_rss, floor)
+                && m.max <= floor
+            {
+                eprintln!(
+                    "Warning: peak memory of `{}` ({:.0} KiB) is no more than the harness's \
+                     own, which the kernel reports for anything smaller. Its real peak is \
+                     likely lower.",
+                    r.command, m.max
+                );
+            }
+        }
+    }
+    let failed: usize = results.iter().map(|r| r.failed).sum();
+    let comparison = compare(&results);
+    let significance = test_significance(&results, baseline.as_ref(), args.alpha);
 
     if args.json {
-        let result = RunResult { exit_code: last_code, times, mean };
-        println!("{}", serde_json::to_string_pretty(&result)?);
+        let report = Report {
+            results,
+            comparison,
+            significance,
+            stopwatch,
+            shell_correction,
+            overhead,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
     } else {
-        println!("Exit code: {:?}", last_code);
-        println!("Runs: {}", args.runs);
-        println!("Times: {:?}", times);
-        println!("Mean: {:.3} sec", mean);
-    }
-
-    // Print Riot/League processes (single snapshot)
-    let mut sys = System::new_all();
-    sys.refresh_all();
-    println!("\nTop Riot / League processes running:");
-    for (pid, process) in sys.processes() {
-        let name_lc = process.name().to_ascii_lowercase();
-        if name_lc.contains("riot") || name_lc.contains("league") {
+        for r in &results {
+            if results.len() > 1 {
+                println!("\n`{}`", r.command);
+            }
+            print_result(r);
+        }
+        if !comparison.is_empty() {
+            print_comparison(&comparison);
+        }
+        if !significance.is_empty() {
+            print_significance(&significance, args.alpha);
+        }
+        if let Some(sw) = &stopwatch {
+            print_stopwatch(sw);
+        }
+        if let Some(o) = overhead {
+            println!(
+                "\nHarness overhead: {:.3} ms ± {:.3} ms per run (not subtracted)",
+                o.mean * 1000.0,
+                o.stddev * 1000.0
+            );
+            if let Some(k) = o.rss_floor_kib {
+                println!("Peak memory can't read below {} KiB, the harness's own", k);
+            }
+        }
+        if let Some(c) = shell_correction {
             println!(
-                "PID: {:<8} Name: {:<25} CPU: {:>5.1}%  Mem: {:>8} KiB",
-                pid.as_u32(),
-                process.name(),
-                process.cpu_usage(),
-                process.memory()
+                "\nShell startup of {:.3} ms subtracted in the summaries without it",
+                c * 1000.0
             );
         }
     }
 
+    // Keeps --json output a single document
+    if !args.json {
+        let interval = args
+            .sample_interval
+            .unwrap_or(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        procs::print_snapshot(&args.filter.proc_filter, interval);
+    }
+
+    // Failed runs make the numbers suspect, so say so in our exit status
+    if failed > 0 && !args.ignore_failure {
+        anyhow::bail!("{} run(s) failed (--ignore-failure to accept them anyway)", failed);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(s: &str) -> Vec<String> {
+        split_command(s).unwrap()
+    }
+
+    #[test]
+    fn split_command_handles_quotes_and_escapes() {
+        assert_eq!(words("sleep 1"), ["sleep", "1"]);
+        assert_eq!(words("  sleep   1  "), ["sleep", "1"]);
+        assert_eq!(words(r#"echo "a b" 'c d'"#), ["echo", "a b", "c d"]);
+        assert_eq!(words(r#"echo a\ b "x\"y" 'x\y'"#), ["echo", "a b", "x\"y", "x\\y"]);
+        assert_eq!(words(r#"echo "" end"#), ["echo", "", "end"]);
+        assert_eq!(words("pre'fix'ed"), ["prefixed"]);
+    }
+
+    #[test]
+    fn split_command_rejects_bad_input() {
+        assert!(split_command("").is_err());
+        assert!(split_command("   ").is_err());
+        assert!(split_command("echo 'open").is_err());
+    }
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 1.5 "), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn parse_precision_percent_or_fraction() {
+        assert_eq!(parse_precision("2%"), Ok(0.02));
+        assert_eq!(parse_precision("0.05"), Ok(0.05));
+        assert_eq!(parse_precision(" 5 % "), Ok(0.05));
+        assert!(parse_precision("0").is_err());
+        assert!(parse_precision("-1%").is_err());
+        assert!(parse_precision("NaN").is_err());
+        assert!(parse_precision("abc").is_err());
+    }
+}
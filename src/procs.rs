@@ -0,0 +1,221 @@
+use regex::{Regex, RegexBuilder};
+use std::io::Write;
+use std::time::Duration;
+use sysinfo::{Pid, Process, ProcessRefreshKind, System, ThreadKind, UpdateKind};
+
+/// One `--proc-filter`: a plain substring, or a regex when prefixed with `re:`.
+/// Both are case-insensitive.
+#[derive(Clone, Debug)]
+pub struct ProcFilter {
+    source: String,
+    re: Regex,
+}
+
+impl ProcFilter {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let pattern = match s.strip_prefix("re:") {
+            Some(re) => re.to_string(),
+            None => regex::escape(s),
+        };
+        let re = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(ProcFilter {
+            source: s.to_string(),
+            re,
+        })
+    }
+
+    /// Name or executable file name only, ignoring the command line
+    pub fn matches_name(&self, process: &Process) -> bool {
+        let exe_name = process
+            .exe()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy());
+        self.re.is_match(process.name()) || exe_name.is_some_and(|n| self.re.is_match(&n))
+    }
+}
+
+/// Matches on the process name, executable path or command line. Threads are
+/// skipped since they share all three with their process.
+pub fn matches(filters: &[ProcFilter], process: &Process) -> bool {
+    if process.thread_kind() == Some(ThreadKind::Userland) {
+        return false;
+    }
+    let exe = process.exe().map(|p| p.to_string_lossy()).unwrap_or_default();
+    let cmdline = process.cmd().join(" ");
+    filters.iter().any(|f| {
+        f.re.is_match(process.name()) || f.re.is_match(&exe) || f.re.is_match(&cmdline)
+    })
+}
+
+/// One matching process over the sampling window
+pub struct ProcUsage {
+    pub pid: u32,
+    pub name: String,
+    /// Percent of a single core, so up to 100% per core in use
+    pub cpu: f32,
+    /// Percent of the whole machine
+    pub cpu_of_machine: f32,
+    pub mem_kib: u64,
+}
+
+pub struct Snapshot {
+    pub procs: Vec<ProcUsage>,
+    /// Whole-machine CPU usage over the window, all processes
+    pub machine_cpu: f32,
+    pub cores: usize,
+}
+
+/// Keeps a `System` around so every sample is measured against the previous one
+pub struct Sampler {
+    sys: System,
+    me: Option<Pid>,
+}
+
+fn refresh_kind() -> ProcessRefreshKind {
+    ProcessRefreshKind::new()
+        .with_cpu()
+        .with_memory()
+        .with_disk_usage()
+        .with_exe(UpdateKind::OnlyIfNotSet)
+        .with_cmd(UpdateKind::OnlyIfNotSet)
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        let mut sys = System::new();
+        sys.refresh_cpu();
+        sys.refresh_processes_specifics(refresh_kind());
+        Sampler {
+            sys,
+            me: sysinfo::get_current_pid().ok(),
+        }
+    }
+
+    /// Usage since the previous call (or since `new`)
+    pub fn sample(&mut self, filters: &[ProcFilter]) -> Snapshot {
+        self.sys.refresh_cpu();
+        self.sys.refresh_processes_specifics(refresh_kind());
+        let cores = self.sys.cpus().len().max(1);
+
+        let procs = self
+            .sys
+            .processes()
+            .iter()
+            // Our own command line contains the filters, so leave ourselves out
+            .filter(|(pid, p)| Some(**pid) != self.me && matches(filters, p))
+            .map(|(pid, p)| ProcUsage {
+                pid: pid.as_u32(),
+                name: p.name().to_string(),
+                cpu: p.cpu_usage(),
+                cpu_of_machine: p.cpu_usage() / cores as f32,
+                mem_kib: p.memory() / 1024,
+            })
+            .collect();
+
+        Snapshot {
+            procs,
+            machine_cpu: self.sys.global_cpu_info().cpu_usage(),
+            cores,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SortBy {
+    Cpu,
+    Mem,
+}
+
+fn filter_names(filters: &[ProcFilter]) -> String {
+    let names: Vec<&str> = filters.iter().map(|f| f.source.as_str()).collect();
+    names.join(", ")
+}
+
+/// Takes two samples `interval` apart so CPU usage covers a real window
+pub fn print_snapshot(filters: &[ProcFilter], interval: Duration) {
+    let mut sampler = Sampler::new();
+    std::thread::sleep(interval);
+    let snap = sampler.sample(filters);
+
+    println!(
+        "\nProcesses matching {} (CPU over {} ms):",
+        filter_names(filters),
+        interval.as_millis()
+    );
+    print_rows(&snap);
+}
+
+/// Redraws the matching processes every `interval` until killed (Ctrl-C)
+pub fn monitor(
+    filters: &[ProcFilter],
+    interval: Duration,
+    sort: SortBy,
+    top: Option<usize>,
+) -> anyhow::Result<()> {
+    let mut sampler = Sampler::new();
+    let mut out = std::io::stdout();
+    loop {
+        std::thread::sleep(interval);
+        let mut snap = sampler.sample(filters);
+        match sort {
+            SortBy::Cpu => snap.procs.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)),
+            SortBy::Mem => snap.procs.sort_by_key(|p| std::cmp::Reverse(p.mem_kib)),
+        }
+        if let Some(n) = top {
+            snap.procs.truncate(n);
+        }
+
+        // Clear the screen and home the cursor before each redraw
+        print!("\x1B[2J\x1B[H");
+        println!(
+            "Processes matching {} (every {} ms, Ctrl-C to quit)\n",
+            filter_names(filters),
+            interval.as_millis()
+        );
+        print_rows(&snap);
+        out.flush()?;
+    }
+}
+
+fn print_rows(snap: &Snapshot) {
+    for p in &snap.procs {
+        println!(
+            "PID: {:<8} Name: {:<25} CPU: {:>5.1}% ({:>4.1}% of machine)  Mem: {:>8} KiB",
+            p.pid, p.name, p.cpu, p.cpu_of_machine, p.mem_kib
+        );
+    }
+    // Not `sum()`: summing no f32s gives -0.0, which prints as "-0.0%"
+    let total = snap.procs.iter().map(|p| p.cpu_of_machine).fold(0.0, |a, b| a + b);
+    println!(
+        "Matched total: {:.1}% of machine ({} cores), machine overall: {:.1}%",
+        total, snap.cores, snap.machine_cpu
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_proc_filters() {
+        // Plain filters are substrings, with regex metacharacters taken literally
+        let plain = ProcFilter::parse("a.b").unwrap();
+        assert!(plain.re.is_match("xa.by"));
+        assert!(!plain.re.is_match("axb"));
+
+        let re = ProcFilter::parse("re:^fire(fox)?$").unwrap();
+        assert!(re.re.is_match("firefox"));
+        assert!(re.re.is_match("fire"));
+        assert!(!re.re.is_match("firefox-bin"));
+
+        // Either way without regard to case
+        assert!(ProcFilter::parse("Chrome").unwrap().re.is_match("chrome"));
+        assert!(ProcFilter::parse("re:^CHROME").unwrap().re.is_match("Chromedriver"));
+
+        assert!(ProcFilter::parse("re:(").is_err());
+        assert!(ProcFilter::parse("(").is_ok());
+    }
+}
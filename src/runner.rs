@@ -0,0 +1,546 @@
+use crate::tree::{self, TraceSample, TreeTracker, TreeUsage};
+use crate::until::{Until, Watch};
+use serde::Serialize;
+use std::fmt;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How each spawn should be watched
+#[derive(Default)]
+pub struct RunOptions {
+    /// Sample the process tree this often while the command runs
+    pub trace_interval: Option<Duration>,
+    /// Follow every descendant and total up their usage
+    pub track_tree: bool,
+    /// Stop the stopwatch on this instead of the command exiting
+    pub until: Option<Until>,
+    /// What happens to the command once a readiness probe is reached
+    pub on_ready: OnReady,
+    /// Kill the command (and its process group) if a run takes longer
+    pub timeout: Option<Duration>,
+    /// How long a command gets between SIGTERM and SIGKILL
+    pub kill_grace: Duration,
+    /// Send the command's stdout to our stderr, keeping our stdout clean
+    pub stdout_to_stderr: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnReady {
+    /// Terminate the command and wait for it to exit
+    #[default]
+    Kill,
+    /// Leave it running and move on
+    Keep,
+}
+
+/// What runs the command line
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Shell {
+    Sh,
+    Bash,
+    /// Run the program directly, no pipes, globs or builtins
+    #[default]
+    None,
+}
+
+impl Shell {
+    /// The argv that runs `script` through this shell, if there is one
+    pub fn wrap(self, script: &str) -> Option<Vec<String>> {
+        let shell = match self {
+            Shell::Sh => "sh",
+            Shell::Bash => "bash",
+            Shell::None => return None,
+        };
+        Some(vec![shell.to_string(), "-c".to_string(), script.to_string()])
+    }
+}
+
+/// How often the tree is polled for --track-tree when not tracing
+const TREE_POLL: Duration = Duration::from_millis(50);
+/// How often an `Until` condition is checked
+const UNTIL_POLL: Duration = Duration::from_millis(10);
+/// How often a run in its own process group checks for Ctrl-C
+const INTERRUPT_POLL: Duration = Duration::from_millis(50);
+/// How long an `Until` condition may stay unmet after the command exited
+/// before we say so
+const PENDING_WARN_AFTER: Duration = Duration::from_secs(10);
+
+/// How a single run ended
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum RunStatus {
+    /// Exited with 0, reached its `until` condition, or was left running
+    Ok,
+    NonZero { code: i32 },
+    /// Killed by a signal we didn't send (Unix only)
+    Signaled { signal: i32 },
+    /// Still running at `--timeout`, so we killed it
+    TimedOut,
+}
+
+impl RunStatus {
+    fn of(status: ExitStatus) -> Self {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return RunStatus::Signaled { signal };
+            }
+        }
+        match status.code() {
+            Some(0) | None => RunStatus::Ok,
+            Some(code) => RunStatus::NonZero { code },
+        }
+    }
+}
+
+impl fmt::Display for RunStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunStatus::Ok => write!(f, "ok"),
+            RunStatus::NonZero { code } => write!(f, "exit code {}", code),
+            RunStatus::Signaled { signal } => write!(f, "killed by signal {}", signal),
+            RunStatus::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// What a single spawn of the benchmarked command gave us
+pub struct Measurement {
+    /// None when the command was left running (`OnReady::Keep`)
+    pub status: Option<ExitStatus>,
+    pub outcome: RunStatus,
+    /// Wall-clock seconds from spawn to exit, or to the `until` condition
+    pub elapsed: f64,
+    /// CPU seconds of the child (and the children it waited for), where available
+    pub user_time: Option<f64>,
+    pub system_time: Option<f64>,
+    /// Peak resident set size in KiB. The kernel reports the largest of the
+    /// child and any descendants it waited for. On Linux this never drops
+    /// below our own RSS at spawn time: std spawns vfork-style, so the
+    /// child's high-water mark starts out at our address space until exec.
+    pub max_rss_kib: Option<u64>,
+    /// Resource samples of the tree, with `trace_interval`
+    pub timeline: Vec<TraceSample>,
+    /// Whole-tree totals, with `track_tree`
+    pub tree: Option<TreeUsage>,
+}
+
+impl Measurement {
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.and_then(|s| s.code())
+    }
+
+    /// The signal that ended the command, whoever sent it (Unix only)
+    pub fn signal(&self) -> Option<i32> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            self.status.and_then(|s| s.signal())
+        }
+        #[cfg(not(unix))]
+        None
+    }
+}
+
+fn build_command(cmd: &[String]) -> Command {
+    #[cfg(target_os = "windows")]
+    {
+        // Builtins like `echo` require running through cmd.exe
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(&cmd[0]).args(&cmd[1..]);
+        c
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        let mut c = Command::new(&cmd[0]);
+        c.args(&cmd[1..]);
+        c
+    }
+}
+
+/// Runs a helper command to completion, untimed and unwatched
+pub fn run_untimed(cmd: &[String], stdout_to_stderr: bool) -> io::Result<ExitStatus> {
+    let mut command = build_command(cmd);
+    if stdout_to_stderr {
+        command.stdout(io::stderr());
+    }
+    command.status()
+}
+
+#[cfg(unix)]
+fn timeval_secs(tv: libc::timeval) -> f64 {
+    tv.tv_sec as f64 + tv.tv_usec as f64 / 1e6
+}
+
+#[cfg(unix)]
+fn maxrss_kib(maxrss: libc::c_long) -> u64 {
+    // macOS reports bytes, everyone else KiB
+    if cfg!(target_os = "macos") {
+        maxrss as u64 / 1024
+    } else {
+        maxrss as u64
+    }
+}
+
+/// Blocks until the child exits; the stopwatch stops right here
+#[cfg(unix)]
+fn wait_child(child: Child, start: Instant) -> io::Result<Measurement> {
+    use std::os::unix::process::ExitStatusExt;
+
+    // wait4 instead of Child::wait so we also get the child's rusage
+    let pid = child.id() as libc::pid_t;
+    let mut status = 0;
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        let ret = unsafe { libc::wait4(pid, &mut status, 0, &mut usage) };
+        if ret == pid {
+            break;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    Ok(Measurement {
+        status: Some(ExitStatus::from_raw(status)),
+        outcome: RunStatus::of(ExitStatus::from_raw(status)),
+        elapsed,
+        user_time: Some(timeval_secs(usage.ru_utime)),
+        system_time: Some(timeval_secs(usage.ru_stime)),
+        max_rss_kib: Some(maxrss_kib(usage.ru_maxrss)),
+        timeline: Vec::new(),
+        tree: None,
+    })
+}
+
+#[cfg(not(unix))]
+fn wait_child(mut child: Child, start: Instant) -> io::Result<Measurement> {
+    let status = child.wait()?;
+    Ok(Measurement {
+        status: Some(status),
+        outcome: RunStatus::of(status),
+        elapsed: start.elapsed().as_secs_f64(),
+        user_time: None,
+        system_time: None,
+        max_rss_kib: None,
+        timeline: Vec::new(),
+        tree: None,
+    })
+}
+
+/// Ctrl-C only reaches the terminal's foreground group, which a command with
+/// its own group isn't in. SIGINT / SIGTERM are passed on to the group, and
+/// the run loop takes it down before we die of the signal ourselves.
+#[cfg(unix)]
+mod interrupt {
+    use std::sync::Once;
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    static GROUP: AtomicI32 = AtomicI32::new(0);
+    static PENDING: AtomicI32 = AtomicI32::new(0);
+    static INSTALL: Once = Once::new();
+
+    extern "C" fn on_signal(sig: libc::c_int) {
+        // Only async-signal-safe calls in here
+        let group = GROUP.load(Ordering::SeqCst);
+        if group > 0 {
+            unsafe {
+                libc::kill(-group, sig);
+            }
+            PENDING.store(sig, Ordering::SeqCst);
+        } else {
+            reraise(sig);
+        }
+    }
+
+    pub fn install() {
+        INSTALL.call_once(|| {
+            let handler = on_signal as extern "C" fn(libc::c_int);
+            for sig in [libc::SIGINT, libc::SIGTERM] {
+                unsafe {
+                    let mut action: libc::sigaction = std::mem::zeroed();
+                    action.sa_sigaction = handler as libc::sighandler_t;
+                    libc::sigaction(sig, &action, std::ptr::null_mut());
+                }
+            }
+        });
+    }
+
+    /// The group that gets the signal, 0 for none
+    pub fn set_group(pid: u32) {
+        GROUP.store(pid as i32, Ordering::SeqCst);
+    }
+
+    /// A signal that arrived while a group was running
+    pub fn pending() -> Option<libc::c_int> {
+        Some(PENDING.load(Ordering::SeqCst)).filter(|&sig| sig != 0)
+    }
+
+    /// Dies of `sig` the way we would have without the handler
+    pub fn reraise(sig: libc::c_int) {
+        unsafe {
+            libc::signal(sig, libc::SIG_DFL);
+            libc::raise(sig);
+        }
+    }
+}
+
+/// Dies of the Ctrl-C / SIGTERM that interrupted a run, if there was one
+pub fn reraise_interrupt() {
+    #[cfg(unix)]
+    if let Some(sig) = interrupt::pending() {
+        interrupt::reraise(sig);
+    }
+}
+
+/// Sends `sig` to the command, or to its whole process group if it has one
+#[cfg(unix)]
+fn send_signal(pid: u32, group: bool, sig: libc::c_int) {
+    let pid = pid as libc::pid_t;
+    unsafe {
+        libc::kill(if group { -pid } else { pid }, sig);
+    }
+}
+
+/// Whether anything in the command's process group is still around
+fn group_alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), 0) == 0
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = pid;
+        false
+    }
+}
+
+/// Asks the command to stop
+fn terminate(pid: u32, group: bool) {
+    #[cfg(unix)]
+    send_signal(pid, group, libc::SIGTERM);
+    #[cfg(windows)]
+    {
+        // /T takes the whole tree down, /F doesn't ask first
+        let _ = group;
+        let _ = Command::new("taskkill")
+            .args(["/PID", &pid.to_string(), "/T", "/F"])
+            .stdout(Stdio::null())
+            .status();
+    }
+}
+
+/// For commands that ignored `terminate`
+fn kill(pid: u32, group: bool) {
+    #[cfg(unix)]
+    send_signal(pid, group, libc::SIGKILL);
+    // taskkill /F already forced it
+    #[cfg(not(unix))]
+    let _ = (pid, group);
+}
+
+pub fn spawn_cross_platform(cmd: &[String], opts: &RunOptions) -> io::Result<Measurement> {
+    let mut command = build_command(cmd);
+    if opts.track_tree {
+        tree::become_subreaper();
+    }
+    let mut watch = opts.until.as_ref().map(Watch::new).transpose()?;
+    if watch.as_ref().is_some_and(|w| w.needs_stdout()) {
+        command.stdout(Stdio::piped());
+    } else if opts.stdout_to_stderr {
+        command.stdout(io::stderr());
+    }
+    let probe = opts.until.as_ref().is_some_and(|u| u.is_probe());
+    // Its own process group, so a timeout or a readiness kill also catches
+    // whatever it started. A background group is stopped if it reads the
+    // terminal, hence no stdin.
+    let group = cfg!(unix) && (opts.timeout.is_some() || probe);
+    #[cfg(unix)]
+    if group {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0).stdin(Stdio::null());
+        interrupt::install();
+    }
+    let start = Instant::now();
+    let mut child = command.spawn()?;
+    let pid = child.id();
+    #[cfg(unix)]
+    if group {
+        interrupt::set_group(pid);
+    }
+    if let (Some(w), Some(out)) = (watch.as_ref(), child.stdout.take()) {
+        w.watch_stdout(out, opts.stdout_to_stderr);
+    }
+
+    // Waiting happens on its own thread so this one is free to sample
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(wait_child(child, start));
+    });
+
+    let sample_every = opts
+        .trace_interval
+        .or(opts.track_tree.then_some(TREE_POLL));
+    let tick = [
+        sample_every,
+        watch.as_ref().map(|_| UNTIL_POLL),
+        group.then_some(INTERRUPT_POLL),
+    ]
+        .into_iter()
+        .flatten()
+        .min();
+    let mut tracker = sample_every.map(|_| TreeTracker::new(pid));
+    let mut last_sample = Instant::now();
+    let mut timeline = Vec::new();
+    let mut exited: Option<Measurement> = None;
+    let mut stopped_at: Option<f64> = None;
+    let deadline = opts.timeout.map(|t| start + t);
+    let mut timed_out = false;
+    let mut interrupted = false;
+    let mut killed_when_ready = false;
+    let mut warned_pending = false;
+    // When a SIGTERM we sent gets followed up with SIGKILL
+    let mut kill_at: Option<Instant> = None;
+
+    loop {
+        let stopping = timed_out || interrupted || killed_when_ready;
+        let done = exited.is_some() && (watch.is_none() || stopped_at.is_some() || stopping);
+        // Once we're killing it, the rest of the group gets its grace period too
+        let lingering = stopping && group && kill_at.is_some() && group_alive(pid);
+        if done && !lingering {
+            break;
+        }
+        if stopped_at.is_some() && probe && opts.on_ready == OnReady::Keep {
+            break;
+        }
+        if exited.is_some() && watch.as_ref().is_some_and(|w| w.impossible()) {
+            eprintln!("The command exited without printing a matching line");
+            break;
+        }
+
+        let next_kill = [deadline.filter(|_| !timed_out), kill_at]
+            .into_iter()
+            .flatten()
+            .min()
+            .map(|at| at.saturating_duration_since(Instant::now()));
+        match ([tick, next_kill].into_iter().flatten().min(), exited.is_some()) {
+            (None, _) => exited = Some(recv(rx.recv().ok())?),
+            (Some(wait), false) => match rx.recv_timeout(wait) {
+                Ok(m) => exited = Some(m?),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => exited = Some(recv(None)?),
+            },
+            // The command is gone but the `until` condition isn't met yet
+            (Some(wait), true) => thread::sleep(wait),
+        }
+
+        // Without a timeout nothing else ends a wait for a process that never comes
+        if let Some(m) = &exited
+            && !warned_pending
+            && deadline.is_none()
+            && stopped_at.is_none()
+            && start.elapsed().as_secs_f64() - m.elapsed >= PENDING_WARN_AFTER.as_secs_f64()
+        {
+            warned_pending = true;
+            eprintln!(
+                "The command exited {}s ago and the `until` condition still isn't met; \
+                 still waiting (use --timeout to give up)",
+                PENDING_WARN_AFTER.as_secs()
+            );
+        }
+
+        if !timed_out && stopped_at.is_none() && deadline.is_some_and(|d| Instant::now() >= d) {
+            timed_out = true;
+            if exited.is_none() || group {
+                terminate(pid, group);
+                kill_at = Some(Instant::now() + opts.kill_grace);
+            }
+        }
+        #[cfg(unix)]
+        if !interrupted && interrupt::pending().is_some() {
+            // The group got the signal already; make sure all of it goes
+            interrupted = true;
+            terminate(pid, group);
+            kill_at = Some(Instant::now() + opts.kill_grace);
+        }
+        if kill_at.is_some_and(|at| Instant::now() >= at) {
+            if exited.is_none() || group {
+                kill(pid, group);
+            }
+            kill_at = None;
+        }
+
+        if let (Some(every), Some(t)) = (sample_every, tracker.as_mut())
+            && last_sample.elapsed() >= every
+        {
+            last_sample = Instant::now();
+            let sample = t.sample(start.elapsed().as_secs_f64());
+            if opts.trace_interval.is_some() {
+                timeline.push(sample);
+            }
+        }
+
+        if !timed_out && stopped_at.is_none() && watch.as_mut().is_some_and(|w| w.reached()) {
+            stopped_at = Some(start.elapsed().as_secs_f64());
+            // Even if the command exited, e.g. a launcher that forked a server
+            if probe && opts.on_ready == OnReady::Kill && (exited.is_none() || group) {
+                killed_when_ready = true;
+                terminate(pid, group);
+                kill_at = Some(Instant::now() + opts.kill_grace);
+            }
+        }
+    }
+
+    // Handing the interrupt back as an error lets the caller clean up before
+    // `reraise_interrupt`
+    #[cfg(unix)]
+    {
+        interrupt::set_group(0);
+        if let Some(sig) = interrupt::pending() {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                format!("interrupted by signal {}", sig),
+            ));
+        }
+    }
+
+    let mut m = match exited {
+        Some(m) => m,
+        // Left running after a readiness probe; its waiter reaps it eventually
+        None => Measurement {
+            status: None,
+            outcome: RunStatus::Ok,
+            elapsed: 0.0,
+            user_time: None,
+            system_time: None,
+            max_rss_kib: None,
+            timeline: Vec::new(),
+            tree: None,
+        },
+    };
+    if let Some(at) = stopped_at {
+        m.elapsed = at;
+        // Whatever it died of afterwards, we probably sent it
+        m.outcome = RunStatus::Ok;
+    }
+    if let (true, Some(t)) = (timed_out, opts.timeout) {
+        m.elapsed = t.as_secs_f64();
+        m.outcome = RunStatus::TimedOut;
+    }
+    m.timeline = timeline;
+    if let (true, Some(t)) = (opts.track_tree, tracker.as_mut()) {
+        let root_cpu = m.user_time.zip(m.system_time).map(|(u, s)| u + s);
+        m.tree = Some(t.finish(root_cpu));
+    }
+    Ok(m)
+}
+
+fn recv<T>(got: Option<io::Result<T>>) -> io::Result<T> {
+    got.unwrap_or_else(|| Err(io::Error::other("waiter thread went away")))
+}
@@ -0,0 +1,577 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Percentile {
+    pub percentile: f64,
+    pub value: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Summary {
+    pub mean: f64,
+    pub stddev: f64,
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    pub percentiles: Vec<Percentile>,
+}
+
+pub fn mean(xs: &[f64]) -> f64 {
+    if xs.is_empty() {
+        return 0.0;
+    }
+    xs.iter().sum::<f64>() / xs.len() as f64
+}
+
+/// Sample standard deviation (n - 1), 0 when there is only one value
+pub fn stddev(xs: &[f64]) -> f64 {
+    if xs.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(xs);
+    let var = xs.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (xs.len() - 1) as f64;
+    var.sqrt()
+}
+
+pub fn sorted(xs: &[f64]) -> Vec<f64> {
+    let mut v = xs.to_vec();
+    v.sort_by(|a, b| a.total_cmp(b));
+    v
+}
+
+/// Percentile `p` (0-100) of already sorted values, linearly interpolated
+pub fn percentile_sorted(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lo = rank.floor() as usize;
+    let hi = rank.ceil() as usize;
+    sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+}
+
+pub fn median(xs: &[f64]) -> f64 {
+    percentile_sorted(&sorted(xs), 50.0)
+}
+
+pub fn summarize(xs: &[f64], percentiles: &[f64]) -> Summary {
+    let s = sorted(xs);
+    Summary {
+        mean: mean(xs),
+        stddev: stddev(xs),
+        median: percentile_sorted(&s, 50.0),
+        min: s.first().copied().unwrap_or(0.0),
+        max: s.last().copied().unwrap_or(0.0),
+        percentiles: percentiles
+            .iter()
+            .map(|&p| Percentile {
+                percentile: p,
+                value: percentile_sorted(&s, p),
+            })
+            .collect(),
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ConfidenceInterval {
+    pub level: f64,
+    pub lower: f64,
+    pub upper: f64,
+}
+
+/// SplitMix64, so resampling is reproducible for a given seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// Percentile bootstrap interval of `statistic` over `xs`
+pub fn bootstrap_ci(
+    xs: &[f64],
+    statistic: fn(&[f64]) -> f64,
+    level: f64,
+    resamples: usize,
+    seed: u64,
+) -> Option<ConfidenceInterval> {
+    if xs.len() < 2 || resamples == 0 {
+        return None;
+    }
+    let mut rng = Rng::new(seed);
+    let mut sample = vec![0.0; xs.len()];
+    let mut estimates: Vec<f64> = (0..resamples)
+        .map(|_| {
+            for v in sample.iter_mut() {
+                *v = xs[rng.below(xs.len())];
+            }
+            statistic(&sample)
+        })
+        .collect();
+    estimates.sort_by(|a, b| a.total_cmp(b));
+
+    let tail = (1.0 - level) / 2.0 * 100.0;
+    Some(ConfidenceInterval {
+        level,
+        lower: percentile_sorted(&estimates, tail),
+        upper: percentile_sorted(&estimates, 100.0 - tail),
+    })
+}
+
+/// Two sided critical value of Student's t for `level` confidence
+pub fn t_critical(level: f64, df: f64) -> f64 {
+    let alpha = 1.0 - level;
+    // p-value falls as t grows, so bisect until it matches alpha
+    let (mut lo, mut hi) = (0.0, 1e3);
+    for _ in 0..100 {
+        let mid = (lo + hi) / 2.0;
+        if incomplete_beta(df / 2.0, 0.5, df / (df + mid * mid)) > alpha {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+/// Half-width of the Student t interval for the mean, relative to the mean
+pub fn relative_half_width(xs: &[f64], level: f64) -> Option<f64> {
+    if xs.len() < 2 {
+        return None;
+    }
+    let m = mean(xs);
+    if m == 0.0 {
+        return None;
+    }
+    let n = xs.len() as f64;
+    Some(t_critical(level, n - 1.0) * stddev(xs) / n.sqrt() / m.abs())
+}
+
+/// Indices of runs flagged by the modified Z-score (> 3.5) or the 1.5 IQR fences
+pub fn outliers(xs: &[f64]) -> Vec<usize> {
+    if xs.len() < 3 {
+        return Vec::new();
+    }
+    let s = sorted(xs);
+    let med = percentile_sorted(&s, 50.0);
+    let q1 = percentile_sorted(&s, 25.0);
+    let q3 = percentile_sorted(&s, 75.0);
+    let iqr = q3 - q1;
+    let (lower, upper) = (q1 - 1.5 * iqr, q3 + 1.5 * iqr);
+
+    let deviations: Vec<f64> = xs.iter().map(|x| (x - med).abs()).collect();
+    let mad = percentile_sorted(&sorted(&deviations), 50.0);
+
+    xs.iter()
+        .enumerate()
+        .filter(|&(_, &x)| {
+            // A zero MAD would make every differing run infinitely far out
+            let z_out = mad > 0.0 && (0.6745 * (x - med) / mad).abs() > 3.5;
+            z_out || x < lower || x > upper
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Ratio `a / b` of two means with its propagated standard deviation
+pub fn ratio(a_mean: f64, a_stddev: f64, b_mean: f64, b_stddev: f64) -> (f64, f64) {
+    let r = a_mean / b_mean;
+    let err = r * ((a_stddev / a_mean).powi(2) + (b_stddev / b_mean).powi(2)).sqrt();
+    (r, err)
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WelchTest {
+    pub t: f64,
+    pub df: f64,
+    pub p_value: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MannWhitney {
+    pub u: f64,
+    pub z: f64,
+    pub p_value: f64,
+    /// The p-value comes from the exact U distribution rather than `z`
+    pub exact: bool,
+}
+
+/// Welch's unequal-variance t-test, two sided
+pub fn welch_t_test(a: &[f64], b: &[f64]) -> Option<WelchTest> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    let va = stddev(a).powi(2) / na;
+    let vb = stddev(b).powi(2) / nb;
+    let se = (va + vb).sqrt();
+    if se == 0.0 {
+        return None;
+    }
+    let t = (mean(a) - mean(b)) / se;
+    let df = (va + vb).powi(2) / (va.powi(2) / (na - 1.0) + vb.powi(2) / (nb - 1.0));
+    let p_value = incomplete_beta(df / 2.0, 0.5, df / (df + t * t));
+    Some(WelchTest { t, df, p_value })
+}
+
+/// Samples up to this size without ties get an exact Mann-Whitney p-value
+const EXACT_U_MAX: usize = 50;
+
+/// Null distribution of U for samples of `m` and `n` without ties, as
+/// probabilities of U = 0..=m*n
+fn u_distribution(m: usize, n: usize) -> Vec<f64> {
+    // The largest value comes from the first sample with probability
+    // i / (i + j), beating all j of the other sample; prev[j] is (i - 1, j)
+    let mut prev: Vec<Vec<f64>> = (0..=n).map(|_| vec![1.0]).collect();
+    for i in 1..=m {
+        let mut cur: Vec<Vec<f64>> = Vec::with_capacity(n + 1);
+        cur.push(vec![1.0]);
+        for j in 1..=n {
+            let from_a = i as f64 / (i + j) as f64;
+            let mut pmf = vec![0.0; i * j + 1];
+            for (k, p) in prev[j].iter().enumerate() {
+                pmf[k + j] += from_a * p;
+            }
+            for (k, p) in cur[j - 1].iter().enumerate() {
+                pmf[k] += (1.0 - from_a) * p;
+            }
+            cur.push(pmf);
+        }
+        prev = cur;
+    }
+    prev.swap_remove(n)
+}
+
+/// Mann-Whitney U test with tie correction, two sided. Exact for small
+/// samples without ties, normal approximation otherwise.
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> Option<MannWhitney> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    let mut all: Vec<(f64, bool)> = a
+        .iter()
+        .map(|&x| (x, true))
+        .chain(b.iter().map(|&x| (x, false)))
+        .collect();
+    all.sort_by(|x, y| x.0.total_cmp(&y.0));
+
+    // Average ranks over ties
+    let mut rank_sum_a = 0.0;
+    let mut tie_term = 0.0;
+    let mut i = 0;
+    while i < all.len() {
+        let mut j = i;
+        while j + 1 < all.len() && all[j + 1].0 == all[i].0 {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let count = (j - i + 1) as f64;
+        tie_term += count.powi(3) - count;
+        rank_sum_a += all[i..=j].iter().filter(|x| x.1).count() as f64 * rank;
+        i = j + 1;
+    }
+
+    let u = rank_sum_a - na * (na + 1.0) / 2.0;
+    let n = na + nb;
+    let mu = na * nb / 2.0;
+    let sigma = (na * nb / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)))).sqrt();
+    if sigma == 0.0 || !sigma.is_finite() {
+        return None;
+    }
+    // Continuity correction towards the mean, but never past it
+    let diff = u - mu;
+    let z = diff.signum() * (diff.abs() - 0.5).max(0.0) / sigma;
+
+    let exact = tie_term == 0.0 && a.len() <= EXACT_U_MAX && b.len() <= EXACT_U_MAX;
+    let p_value = if exact {
+        let pmf = u_distribution(a.len(), b.len());
+        // Without ties U is a whole number
+        let k = u.round() as usize;
+        let below: f64 = pmf[..=k].iter().sum();
+        let above: f64 = pmf[k..].iter().sum();
+        (2.0 * below.min(above)).min(1.0)
+    } else {
+        erfc(z.abs() / std::f64::consts::SQRT_2).min(1.0)
+    };
+    Some(MannWhitney {
+        u,
+        z,
+        p_value,
+        exact,
+    })
+}
+
+/// Cohen's d using the pooled standard deviation
+pub fn cohens_d(a: &[f64], b: &[f64]) -> f64 {
+    let (na, nb) = (a.len() as f64, b.len() as f64);
+    if na + nb <= 2.0 {
+        return 0.0;
+    }
+    let pooled = (((na - 1.0) * stddev(a).powi(2) + (nb - 1.0) * stddev(b).powi(2))
+        / (na + nb - 2.0))
+        .sqrt();
+    if pooled == 0.0 {
+        return 0.0;
+    }
+    (mean(a) - mean(b)) / pooled
+}
+
+/// Rank-biserial correlation from a Mann-Whitney U for `a`
+pub fn rank_biserial(u: f64, na: usize, nb: usize) -> f64 {
+    2.0 * u / (na * nb) as f64 - 1.0
+}
+
+fn ln_gamma(x: f64) -> f64 {
+    // Lanczos approximation (g = 7)
+    const COEF: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let mut sum = COEF[0];
+    for (i, c) in COEF.iter().enumerate().skip(1) {
+        sum += c / (x + i as f64);
+    }
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Regularized incomplete beta function I_x(a, b)
+fn incomplete_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_continued_fraction(a, b, x) / a
+    } else {
+        1.0 - front * beta_continued_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+fn beta_continued_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let mut c = 1.0;
+    let mut d = 1.0 - (a + b) * x / (a + 1.0);
+    if d.abs() < TINY {
+        d = TINY;
+    }
+    d = 1.0 / d;
+    let mut h = d;
+    for m in 1..300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+        let aa = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 + aa * d;
+        d = if d.abs() < TINY { TINY } else { d };
+        c = 1.0 + aa / c;
+        c = if c.abs() < TINY { TINY } else { c };
+        d = 1.0 / d;
+        h *= d * c;
+        let aa = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 + aa * d;
+        d = if d.abs() < TINY { TINY } else { d };
+        c = 1.0 + aa / c;
+        c = if c.abs() < TINY { TINY } else { c };
+        d = 1.0 / d;
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    h
+}
+
+/// Complementary error function (Numerical Recipes erfcc, ~1e-7 accuracy)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t
+        * (-z * z - 1.265_512_23
+            + t * (1.000_023_68
+                + t * (0.374_091_96
+                    + t * (0.096_784_18
+                        + t * (-0.186_288_06
+                            + t * (0.278_868_07
+                                + t * (-1.135_203_98
+                                    + t * (1.488_515_87
+                                        + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+            .exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64, tol: f64) {
+        assert!(
+            (actual - expected).abs() <= tol,
+            "expected {expected}, got {actual}"
+        );
+    }
+
+    #[test]
+    fn percentiles_interpolate_linearly() {
+        let xs = [1.0, 2.0, 3.0, 4.0];
+        assert_close(percentile_sorted(&xs, 0.0), 1.0, 1e-12);
+        assert_close(percentile_sorted(&xs, 25.0), 1.75, 1e-12);
+        assert_close(percentile_sorted(&xs, 50.0), 2.5, 1e-12);
+        assert_close(percentile_sorted(&xs, 100.0), 4.0, 1e-12);
+        assert_close(percentile_sorted(&[7.0], 90.0), 7.0, 1e-12);
+        assert_close(median(&[3.0, 1.0, 2.0]), 2.0, 1e-12);
+    }
+
+    #[test]
+    fn ln_gamma_matches_known_values() {
+        // ln(4!) and ln(sqrt(pi))
+        assert_close(ln_gamma(5.0), 24f64.ln(), 1e-10);
+        assert_close(ln_gamma(0.5), 0.572_364_942_924_700_1, 1e-10);
+        assert_close(ln_gamma(1.0), 0.0, 1e-10);
+    }
+
+    #[test]
+    fn erfc_matches_known_values() {
+        assert_close(erfc(0.0), 1.0, 1e-7);
+        assert_close(erfc(0.5), 0.479_500_122_186_953_5, 1e-7);
+        assert_close(erfc(1.0), 0.157_299_207_050_285_1, 1e-7);
+        assert_close(erfc(-1.0), 1.842_700_792_949_715, 1e-7);
+    }
+
+    #[test]
+    fn incomplete_beta_matches_closed_forms() {
+        // I_x(1, 1) = x
+        assert_close(incomplete_beta(1.0, 1.0, 0.3), 0.3, 1e-10);
+        // Symmetric around 0.5
+        assert_close(incomplete_beta(2.0, 2.0, 0.5), 0.5, 1e-10);
+        // Binomial sum: P(Bin(4, 0.3) >= 2)
+        assert_close(incomplete_beta(2.0, 3.0, 0.3), 0.3483, 1e-10);
+        assert_close(incomplete_beta(2.0, 3.0, 0.0), 0.0, 1e-12);
+        assert_close(incomplete_beta(2.0, 3.0, 1.0), 1.0, 1e-12);
+    }
+
+    #[test]
+    fn welch_on_shifted_samples() {
+        let w = welch_t_test(&[1.0, 2.0, 3.0, 4.0, 5.0], &[2.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert_close(w.t, -1.0, 1e-12);
+        assert_close(w.df, 8.0, 1e-12);
+        assert_close(w.p_value, 0.3466, 1e-4);
+        assert!(welch_t_test(&[1.0], &[2.0, 3.0]).is_none());
+    }
+
+    #[test]
+    fn t_critical_matches_tables() {
+        assert_close(t_critical(0.95, 4.0), 2.776, 1e-3);
+        assert_close(t_critical(0.95, 30.0), 2.042, 1e-3);
+        assert_close(t_critical(0.99, 10.0), 3.169, 1e-3);
+    }
+
+    #[test]
+    fn mann_whitney_exact_without_ties() {
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [6.0, 7.0, 8.0, 9.0, 10.0];
+        let m = mann_whitney(&a, &b).unwrap();
+        assert!(m.exact);
+        assert_close(m.u, 0.0, 1e-12);
+        // Two of the C(10, 5) = 252 orderings are this extreme
+        assert_close(m.p_value, 2.0 / 252.0, 1e-12);
+        assert_close(mann_whitney(&b, &a).unwrap().p_value, 2.0 / 252.0, 1e-12);
+        let small = mann_whitney(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]).unwrap();
+        assert_close(small.p_value, 0.1, 1e-12);
+        // U right at its mean
+        let middle = mann_whitney(&[1.0, 4.0], &[2.0, 3.0]).unwrap();
+        assert_close(middle.p_value, 1.0, 1e-12);
+    }
+
+    #[test]
+    fn mann_whitney_tie_corrected() {
+        let m = mann_whitney(&[1.0, 2.0, 2.0, 3.0, 4.0], &[2.0, 3.0, 3.0, 4.0, 5.0, 6.0]).unwrap();
+        assert!(!m.exact);
+        assert_close(m.u, 6.5, 1e-12);
+        assert_close(m.z, -1.491_418_316, 1e-6);
+        assert_close(m.p_value, 0.135_851_702, 1e-6);
+    }
+
+    #[test]
+    fn mann_whitney_continuity_stops_at_the_mean() {
+        let xs = [1.0, 2.0, 2.0, 3.0];
+        let m = mann_whitney(&xs, &xs).unwrap();
+        assert_close(m.z, 0.0, 1e-12);
+        assert_close(m.p_value, 1.0, 1e-12);
+    }
+
+    #[test]
+    fn outliers_past_the_iqr_fences() {
+        // 20 is past q3 + 1.5 IQR = 16 but only 3.1 modified Z-scores out
+        let xs = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 20.0];
+        assert_eq!(outliers(&xs), vec![10]);
+    }
+
+    #[test]
+    fn outliers_by_modified_z_score() {
+        // 30 is inside the fences (31.25) but 4.3 modified Z-scores out
+        let xs = [0.0, 1.0, 3.0, 4.0, 5.0, 12.0, 20.0, 30.0];
+        assert_eq!(outliers(&xs), vec![7]);
+    }
+
+    #[test]
+    fn outliers_with_zero_mad() {
+        // The Z-score is skipped rather than flagging everything; the fences,
+        // collapsed onto the median, still catch a run that differs
+        assert!(outliers(&[5.0; 6]).is_empty());
+        assert_eq!(outliers(&[5.0, 5.0, 5.0, 6.0, 5.0, 5.0]), vec![3]);
+    }
+
+    #[test]
+    fn outliers_need_three_runs() {
+        assert!(outliers(&[]).is_empty());
+        assert!(outliers(&[1.0, 100.0]).is_empty());
+    }
+
+    #[test]
+    fn bootstrap_is_seeded() {
+        let xs = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        let a = bootstrap_ci(&xs, mean, 0.95, 2000, 42).unwrap();
+        let b = bootstrap_ci(&xs, mean, 0.95, 2000, 42).unwrap();
+        let c = bootstrap_ci(&xs, mean, 0.95, 2000, 7).unwrap();
+        assert_eq!((a.lower, a.upper), (b.lower, b.upper));
+        assert_ne!((a.lower, a.upper), (c.lower, c.upper));
+        assert!(a.lower < mean(&xs) && mean(&xs) < a.upper);
+        assert!(bootstrap_ci(&[1.0], mean, 0.95, 2000, 42).is_none());
+        assert!(bootstrap_ci(&xs, mean, 0.95, 0, 42).is_none());
+    }
+}
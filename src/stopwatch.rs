@@ -0,0 +1,51 @@
+use crate::trigger::{Mark, Trigger};
+use serde::Serialize;
+use std::io;
+use std::time::Instant;
+
+#[derive(Serialize, Debug)]
+pub struct Lap {
+    pub name: String,
+    /// Seconds since start
+    pub at: f64,
+    /// Seconds since the previous lap
+    pub split: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Stopwatch {
+    /// Every lap in order, the final one being the stop
+    pub laps: Vec<Lap>,
+    pub total: f64,
+}
+
+/// Times marks from `trigger` until it says stop; `hint` says how to drive it
+pub fn run(trigger: &mut Trigger, hint: &str) -> io::Result<Stopwatch> {
+    eprintln!("Stopwatch: {}", hint);
+    while trigger.next(false)? != Mark::Start {}
+    let start = Instant::now();
+    eprintln!("Started");
+
+    let mut laps: Vec<Lap> = Vec::new();
+    loop {
+        let mark = trigger.next(true)?;
+        let at = start.elapsed().as_secs_f64();
+        let split = at - laps.last().map_or(0.0, |l| l.at);
+        let (name, stop) = match mark {
+            Mark::Start => continue,
+            Mark::Lap(name) => (
+                name.unwrap_or_else(|| format!("lap {}", laps.len() + 1)),
+                false,
+            ),
+            Mark::Stop => ("stop".to_string(), true),
+        };
+        eprintln!("{}: {:.3} s (+{:.3} s)", name, at, split);
+        laps.push(Lap { name, at, split });
+        if stop {
+            break;
+        }
+    }
+
+    let total = laps.last().map_or(0.0, |l| l.at);
+    Ok(Stopwatch { laps, total })
+}
@@ -0,0 +1,231 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use sysinfo::{Pid, ProcessRefreshKind, System, ThreadKind};
+
+/// Resource usage of the whole process tree at one point in a run
+#[derive(Serialize, Debug, Clone)]
+pub struct TraceSample {
+    /// Seconds since the command was spawned
+    pub t: f64,
+    pub processes: usize,
+    /// Percent of a single core, summed over the tree
+    pub cpu: f32,
+    pub rss_kib: u64,
+    /// Only known on Linux
+    pub threads: Option<usize>,
+    /// Cumulative bytes read / written by the tree so far
+    pub read_bytes: u64,
+    pub written_bytes: u64,
+}
+
+/// Totals for everything one run started
+#[derive(Serialize, Debug, Clone)]
+pub struct TreeUsage {
+    /// Processes seen in the tree, the command itself included
+    pub processes: usize,
+    /// Largest combined RSS of the tree at any sample
+    pub peak_rss_kib: u64,
+    /// User + system CPU seconds of the whole tree (Linux only)
+    pub cpu_time: Option<f64>,
+    /// Descendants still alive when the command exited
+    pub still_running: usize,
+}
+
+/// Follows a spawned command and everything it starts
+pub struct TreeTracker {
+    sys: System,
+    root: Pid,
+    me: Option<Pid>,
+    /// Our children from before this run, e.g. leftovers of earlier runs
+    preexisting: HashSet<Pid>,
+    seen: HashSet<Pid>,
+    peak_rss_kib: u64,
+    /// CPU seconds of descendants we reaped ourselves
+    reaped_cpu: f64,
+    /// Latest CPU seconds of live descendants (not the root)
+    live_cpu: HashMap<Pid, f64>,
+}
+
+fn refresh_kind() -> ProcessRefreshKind {
+    ProcessRefreshKind::new()
+        .with_cpu()
+        .with_memory()
+        .with_disk_usage()
+}
+
+/// Makes orphaned descendants re-parent to us instead of init, so helpers
+/// left behind by a launcher stay in the tree (Linux only)
+pub fn become_subreaper() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+    }
+}
+
+/// utime + stime + cutime + cstime from /proc/<pid>/stat, in seconds
+#[cfg(target_os = "linux")]
+fn proc_cpu_secs(pid: Pid) -> Option<f64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid.as_u32())).ok()?;
+    // The command name can contain spaces, so split after its closing paren
+    let fields: Vec<&str> = stat.get(stat.rfind(')')? + 2..)?.split_whitespace().collect();
+    let ticks: u64 = fields
+        .get(11..15)?
+        .iter()
+        .map(|f| f.parse::<i64>().unwrap_or(0).max(0) as u64)
+        .sum();
+    let per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    Some(ticks as f64 / per_sec.max(1) as f64)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn proc_cpu_secs(_pid: Pid) -> Option<f64> {
+    None
+}
+
+impl TreeTracker {
+    pub fn new(root: u32) -> Self {
+        let mut sys = System::new();
+        sys.refresh_processes_specifics(refresh_kind());
+        let root = Pid::from_u32(root);
+        let me = sysinfo::get_current_pid().ok();
+        let preexisting = sys
+            .processes()
+            .iter()
+            .filter(|(pid, p)| **pid != root && me.is_some() && p.parent() == me)
+            .map(|(pid, _)| *pid)
+            .collect();
+        TreeTracker {
+            sys,
+            root,
+            me,
+            preexisting,
+            seen: HashSet::from([root]),
+            peak_rss_kib: 0,
+            reaped_cpu: 0.0,
+            live_cpu: HashMap::new(),
+        }
+    }
+
+    /// Pids of the root and all its live descendants
+    fn members(&self) -> HashSet<Pid> {
+        let parents: HashMap<Pid, Pid> = self
+            .sys
+            .processes()
+            .iter()
+            // Threads show up as their own entries on Linux
+            .filter(|(_, p)| p.thread_kind() != Some(ThreadKind::Userland))
+            .filter_map(|(pid, p)| p.parent().map(|parent| (*pid, parent)))
+            .collect();
+
+        // Anything already in the tree, plus orphans that were re-parented to us
+        let is_anchor = |pid: Pid| {
+            self.seen.contains(&pid)
+                || (self.me.is_some()
+                    && parents.get(&pid).copied() == self.me
+                    && !self.preexisting.contains(&pid))
+        };
+
+        let mut members = HashSet::new();
+        for &pid in parents.keys() {
+            let mut cur = pid;
+            // Bounded walk in case of a parent cycle from pid reuse
+            for _ in 0..parents.len() + 1 {
+                if is_anchor(cur) {
+                    members.insert(pid);
+                    break;
+                }
+                match parents.get(&cur) {
+                    Some(&parent) => cur = parent,
+                    None => break,
+                }
+            }
+        }
+        members.retain(|pid| self.sys.process(*pid).is_some());
+        members
+    }
+
+    /// Reaps exited orphans that were re-parented to us and keeps their CPU time
+    #[cfg(unix)]
+    fn reap_orphans(&mut self) {
+        let Some(me) = self.me else { return };
+        let children: Vec<Pid> = self
+            .sys
+            .processes()
+            .iter()
+            .filter(|(pid, p)| **pid != self.root && p.parent() == Some(me))
+            .map(|(pid, _)| *pid)
+            .collect();
+
+        for pid in children {
+            let mut status = 0;
+            let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+            let raw = pid.as_u32() as libc::pid_t;
+            let ret = unsafe { libc::wait4(raw, &mut status, libc::WNOHANG, &mut usage) };
+            if ret == raw && !self.preexisting.contains(&pid) {
+                self.seen.insert(pid);
+                self.live_cpu.remove(&pid);
+                let secs = |tv: libc::timeval| tv.tv_sec as f64 + tv.tv_usec as f64 / 1e6;
+                self.reaped_cpu += secs(usage.ru_utime) + secs(usage.ru_stime);
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn reap_orphans(&mut self) {}
+
+    pub fn sample(&mut self, t: f64) -> TraceSample {
+        self.sys.refresh_processes_specifics(refresh_kind());
+        self.reap_orphans();
+        let members = self.members();
+        self.seen.extend(members.iter().copied());
+
+        let mut sample = TraceSample {
+            t,
+            processes: members.len(),
+            cpu: 0.0,
+            rss_kib: 0,
+            threads: None,
+            read_bytes: 0,
+            written_bytes: 0,
+        };
+        for (pid, p) in members.iter().filter_map(|pid| Some((*pid, self.sys.process(*pid)?))) {
+            sample.cpu += p.cpu_usage();
+            sample.rss_kib += p.memory() / 1024;
+            let io = p.disk_usage();
+            sample.read_bytes += io.total_read_bytes;
+            sample.written_bytes += io.total_written_bytes;
+            if let Some(tasks) = p.tasks() {
+                // The main thread is not part of `tasks`
+                *sample.threads.get_or_insert(0) += tasks.len() + 1;
+            }
+            if pid != self.root
+                && let Some(secs) = proc_cpu_secs(pid)
+            {
+                self.live_cpu.insert(pid, secs);
+            }
+        }
+        self.peak_rss_kib = self.peak_rss_kib.max(sample.rss_kib);
+        sample
+    }
+
+    /// Call once the root has exited; `root_cpu` comes from its rusage
+    pub fn finish(&mut self, root_cpu: Option<f64>) -> TreeUsage {
+        self.sample(0.0);
+        let alive: HashSet<Pid> = self.members();
+        let live_cpu: f64 = self
+            .live_cpu
+            .iter()
+            .filter(|(pid, _)| alive.contains(pid))
+            .map(|(_, secs)| secs)
+            .sum();
+
+        TreeUsage {
+            processes: self.seen.len(),
+            peak_rss_kib: self.peak_rss_kib,
+            cpu_time: root_cpu
+                .filter(|_| cfg!(target_os = "linux"))
+                .map(|root| root + self.reaped_cpu + live_cpu),
+            still_running: alive.iter().filter(|pid| **pid != self.root).count(),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::io::{self, BufRead, Lines, StdinLock};
+#[cfg(unix)]
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+};
+
+/// A mark from whoever is driving the stopwatch
+#[derive(Debug, PartialEq)]
+pub enum Mark {
+    Start,
+    /// A lap, optionally named
+    Lap(Option<String>),
+    Stop,
+}
+
+/// `--trigger` as given on the command line
+#[derive(Clone, Debug)]
+pub enum TriggerSpec {
+    Stdin,
+    #[cfg(unix)]
+    Signal,
+    #[cfg(unix)]
+    Fifo(PathBuf),
+    #[cfg(unix)]
+    Socket(PathBuf),
+}
+
+impl TriggerSpec {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.split_once(':') {
+            None if s == "stdin" => Ok(TriggerSpec::Stdin),
+            #[cfg(unix)]
+            None if s == "signal" => Ok(TriggerSpec::Signal),
+            #[cfg(unix)]
+            Some(("fifo", path)) => Ok(TriggerSpec::Fifo(path.into())),
+            #[cfg(unix)]
+            Some(("socket", path)) => Ok(TriggerSpec::Socket(path.into())),
+            _ => Err(format!(
+                "unknown trigger `{s}` (use stdin, signal, fifo:PATH or socket:PATH)"
+            )),
+        }
+    }
+
+    /// One line telling the user how to drive this trigger
+    pub fn describe(&self) -> String {
+        match self {
+            TriggerSpec::Stdin => "ENTER starts and marks laps (type a name first to name it), \
+                                   q stops"
+                .to_string(),
+            #[cfg(unix)]
+            TriggerSpec::Signal => format!(
+                "kill -USR1 {pid} starts and marks laps, kill -USR2 {pid} stops",
+                pid = std::process::id()
+            ),
+            #[cfg(unix)]
+            TriggerSpec::Fifo(path) => format!(
+                "write `start`, `lap [name]` or `stop` lines to {}",
+                path.display()
+            ),
+            #[cfg(unix)]
+            TriggerSpec::Socket(path) => format!(
+                "send `start`, `lap [name]` or `stop` lines to the socket {}",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Where start / lap / stop marks come from
+pub enum Trigger {
+    /// ENTER starts and laps, typing a name first names the lap, `q` stops
+    Stdin(Lines<StdinLock<'static>>),
+    /// SIGUSR1 starts and laps, SIGUSR2 stops; read back through a self-pipe
+    #[cfg(unix)]
+    Signal(File),
+    /// `start` / `lap [name]` / `stop` lines, reopened after every writer
+    #[cfg(unix)]
+    Fifo {
+        path: PathBuf,
+        lines: Option<Lines<BufReader<File>>>,
+    },
+    /// The same lines, over any number of connections
+    #[cfg(unix)]
+    Socket {
+        listener: UnixListener,
+        lines: Option<Lines<BufReader<UnixStream>>>,
+    },
+}
+
+#[cfg(unix)]
+mod signal {
+    use std::sync::atomic::{AtomicI32, Ordering};
+
+    static PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn on_signal(sig: libc::c_int) {
+        // Only async-signal-safe calls in here
+        let byte: u8 = if sig == libc::SIGUSR2 { b'2' } else { b'1' };
+        let fd = PIPE_WRITE.load(Ordering::SeqCst);
+        unsafe {
+            libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+        }
+    }
+
+    /// A pipe that isn't inherited by the benchmarked commands
+    fn cloexec_pipe() -> std::io::Result<[libc::c_int; 2]> {
+        let mut fds = [0; 2];
+        #[cfg(target_os = "linux")]
+        let ret = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) };
+        #[cfg(not(target_os = "linux"))]
+        let ret = unsafe {
+            let ret = libc::pipe(fds.as_mut_ptr());
+            if ret == 0 {
+                for fd in fds {
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                }
+            }
+            ret
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(fds)
+    }
+
+    /// Installs SIGUSR1 / SIGUSR2 handlers, returning the pipe's read end
+    pub fn install() -> std::io::Result<libc::c_int> {
+        let fds = cloexec_pipe()?;
+        // A full pipe drops the mark instead of hanging the handler
+        unsafe {
+            let flags = libc::fcntl(fds[1], libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fds[1], libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        PIPE_WRITE.store(fds[1], Ordering::SeqCst);
+        for sig in [libc::SIGUSR1, libc::SIGUSR2] {
+            let handler = on_signal as extern "C" fn(libc::c_int);
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handler as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                if libc::sigaction(sig, &action, std::ptr::null_mut()) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(fds[0])
+    }
+}
+
+/// `start`, `lap [name]` and `stop` for the FIFO and socket triggers
+#[cfg(unix)]
+fn parse_line(line: &str) -> Option<Mark> {
+    let line = line.trim();
+    let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+    match word {
+        "start" => Some(Mark::Start),
+        "lap" => Some(Mark::Lap(Some(rest.trim()).filter(|n| !n.is_empty()).map(String::from))),
+        "stop" => Some(Mark::Stop),
+        "" => None,
+        other => {
+            eprintln!("Ignoring unknown trigger command `{}`", other);
+            None
+        }
+    }
+}
+
+impl Trigger {
+    pub fn open(spec: &TriggerSpec) -> io::Result<Self> {
+        match spec {
+            TriggerSpec::Stdin => Ok(Trigger::Stdin(io::stdin().lock().lines())),
+            #[cfg(unix)]
+            TriggerSpec::Signal => {
+                use std::os::fd::FromRawFd;
+                let fd = signal::install()?;
+                Ok(Trigger::Signal(unsafe { File::from_raw_fd(fd) }))
+            }
+            #[cfg(unix)]
+            TriggerSpec::Fifo(path) => {
+                match std::fs::metadata(path) {
+                    Ok(meta) => {
+                        // A regular file would hit EOF on every reopen and spin
+                        use std::os::unix::fs::FileTypeExt;
+                        if !meta.file_type().is_fifo() {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AlreadyExists,
+                                format!("{} exists and is not a FIFO", path.display()),
+                            ));
+                        }
+                    }
+                    Err(_) => {
+                        use std::os::unix::ffi::OsStrExt;
+                        let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+                        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                }
+                Ok(Trigger::Fifo {
+                    path: path.clone(),
+                    lines: None,
+                })
+            }
+            #[cfg(unix)]
+            TriggerSpec::Socket(path) => {
+                // A socket file left over from an earlier run would make bind
+                // fail, but anything else at the path is not ours to delete
+                if let Ok(meta) = std::fs::symlink_metadata(path) {
+                    use std::os::unix::fs::FileTypeExt;
+                    if !meta.file_type().is_socket() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} exists and is not a socket", path.display()),
+                        ));
+                    }
+                    std::fs::remove_file(path)?;
+                }
+                Ok(Trigger::Socket {
+                    listener: UnixListener::bind(path)?,
+                    lines: None,
+                })
+            }
+        }
+    }
+
+    /// Blocks until the next mark. `started` tells stdin whether ENTER means
+    /// start or lap.
+    pub fn next(&mut self, started: bool) -> io::Result<Mark> {
+        match self {
+            Trigger::Stdin(lines) => {
+                let Some(line) = lines.next().transpose()? else {
+                    // Ctrl-D / closed stdin
+                    return Ok(Mark::Stop);
+                };
+                let line = line.trim();
+                Ok(match line {
+                    _ if !started => Mark::Start,
+                    "q" | "stop" => Mark::Stop,
+                    "" => Mark::Lap(None),
+                    name => Mark::Lap(Some(name.to_string())),
+                })
+            }
+            #[cfg(unix)]
+            Trigger::Signal(pipe) => {
+                let mut byte = [0u8];
+                pipe.read_exact(&mut byte)?;
+                Ok(match (byte[0], started) {
+                    (b'2', _) => Mark::Stop,
+                    (_, false) => Mark::Start,
+                    (_, true) => Mark::Lap(None),
+                })
+            }
+            #[cfg(unix)]
+            Trigger::Fifo { path, lines } => loop {
+                if lines.is_none() {
+                    // Blocks until a writer opens the FIFO
+                    *lines = Some(BufReader::new(File::open(&*path)?).lines());
+                }
+                match lines.as_mut().and_then(|l| l.next()).transpose()? {
+                    Some(line) => {
+                        if let Some(mark) = parse_line(&line) {
+                            return Ok(mark);
+                        }
+                    }
+                    // The writer went away, wait for the next one
+                    None => *lines = None,
+                }
+            },
+            #[cfg(unix)]
+            Trigger::Socket { listener, lines } => loop {
+                if lines.is_none() {
+                    let (stream, _) = listener.accept()?;
+                    *lines = Some(BufReader::new(stream).lines());
+                }
+                match lines.as_mut().and_then(|l| l.next()).transpose()? {
+                    Some(line) => {
+                        if let Some(mark) = parse_line(&line) {
+                            return Ok(mark);
+                        }
+                    }
+                    None => *lines = None,
+                }
+            },
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_commands() {
+        assert_eq!(parse_line("start"), Some(Mark::Start));
+        assert_eq!(parse_line("  stop \n"), Some(Mark::Stop));
+        assert_eq!(parse_line("lap"), Some(Mark::Lap(None)));
+        assert_eq!(parse_line("lap   "), Some(Mark::Lap(None)));
+        assert_eq!(parse_line("lap login screen"), Some(Mark::Lap(Some("login screen".into()))));
+        assert_eq!(parse_line(""), None);
+        assert_eq!(parse_line("jump"), None);
+    }
+
+    #[test]
+    fn parse_trigger_specs() {
+        assert!(matches!(TriggerSpec::parse("stdin"), Ok(TriggerSpec::Stdin)));
+        assert!(matches!(TriggerSpec::parse("signal"), Ok(TriggerSpec::Signal)));
+        let fifo = TriggerSpec::parse("fifo:/tmp/f");
+        assert!(matches!(fifo, Ok(TriggerSpec::Fifo(p)) if p == std::path::Path::new("/tmp/f")));
+        assert!(matches!(TriggerSpec::parse("socket:/tmp/s"), Ok(TriggerSpec::Socket(_))));
+        assert!(TriggerSpec::parse("tcp:1234").is_err());
+    }
+}
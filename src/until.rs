@@ -0,0 +1,186 @@
+use crate::procs::ProcFilter;
+use regex::Regex;
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessStatus, System, ThreadKind, UpdateKind};
+
+/// Something other than the command exiting that stops a run's stopwatch
+#[derive(Clone, Debug)]
+pub enum Until {
+    /// A new matching process shows up anywhere on the system
+    ProcessAppears(ProcFilter),
+    /// A new matching process showed up and all of them have exited again
+    ProcessExits(ProcFilter),
+    /// The command prints a matching line to stdout
+    Stdout(Regex),
+    /// The file is created, or modified if it already existed
+    File(PathBuf),
+    /// Something accepts connections on this local TCP port
+    Port(u16),
+}
+
+impl Until {
+    /// Readiness probes, as opposed to the process conditions. The command is
+    /// usually still running once one of these is reached.
+    pub fn is_probe(&self) -> bool {
+        matches!(self, Until::Stdout(_) | Until::File(_) | Until::Port(_))
+    }
+}
+
+enum State {
+    Procs {
+        sys: Box<System>,
+        before: HashSet<Pid>,
+        seen: bool,
+    },
+    Stdout {
+        hit: Arc<AtomicBool>,
+        closed: Arc<AtomicBool>,
+    },
+    File(Option<SystemTime>),
+    Port,
+}
+
+/// Polls for an `Until`. Processes that were already running when the watch
+/// was created never count, so an open client doesn't end the run early.
+pub struct Watch {
+    until: Until,
+    state: State,
+}
+
+fn refresh_kind() -> ProcessRefreshKind {
+    ProcessRefreshKind::new().with_exe(UpdateKind::OnlyIfNotSet)
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn accepts(port: u16) -> bool {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    TcpStream::connect_timeout(&addr, Duration::from_millis(50)).is_ok()
+}
+
+/// How long a run waits for the port to be free, e.g. for the previous run's
+/// server to shut down
+const PORT_FREE_WAIT: Duration = Duration::from_secs(5);
+
+impl Watch {
+    /// Create this before spawning so the command's processes count as new.
+    /// Fails if the port of a port probe is still taken after a short wait,
+    /// since connecting would succeed before the command did anything.
+    pub fn new(until: &Until) -> io::Result<Self> {
+        let state = match until {
+            Until::ProcessAppears(_) | Until::ProcessExits(_) => {
+                let mut sys = System::new();
+                sys.refresh_processes_specifics(refresh_kind());
+                let before = sys.processes().keys().copied().collect();
+                State::Procs {
+                    sys: Box::new(sys),
+                    before,
+                    seen: false,
+                }
+            }
+            Until::Stdout(_) => State::Stdout {
+                hit: Arc::new(AtomicBool::new(false)),
+                closed: Arc::new(AtomicBool::new(false)),
+            },
+            Until::File(path) => State::File(modified(path)),
+            Until::Port(port) => {
+                let waiting = Instant::now();
+                while accepts(*port) {
+                    if waiting.elapsed() >= PORT_FREE_WAIT {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AddrInUse,
+                            format!("port {} already accepts connections before the run", port),
+                        ));
+                    }
+                    std::thread::sleep(Duration::from_millis(50));
+                }
+                State::Port
+            }
+        };
+        Ok(Watch {
+            until: until.clone(),
+            state,
+        })
+    }
+
+    /// Whether the command's stdout has to be piped through `watch_stdout`
+    pub fn needs_stdout(&self) -> bool {
+        matches!(self.state, State::Stdout { .. })
+    }
+
+    /// The condition can no longer be met: stdout closed without a match
+    pub fn impossible(&self) -> bool {
+        match &self.state {
+            State::Stdout { hit, closed } => {
+                closed.load(Ordering::SeqCst) && !hit.load(Ordering::SeqCst)
+            }
+            _ => false,
+        }
+    }
+
+    /// Echoes the command's stdout (to our stderr if asked) while looking for
+    /// the pattern
+    pub fn watch_stdout(&self, out: impl Read + Send + 'static, to_stderr: bool) {
+        let (Until::Stdout(re), State::Stdout { hit, closed }) = (&self.until, &self.state) else {
+            return;
+        };
+        let (re, hit, closed) = (re.clone(), hit.clone(), closed.clone());
+        std::thread::spawn(move || {
+            // Keep draining after a match so the command never blocks on a full pipe
+            for line in BufReader::new(out).lines() {
+                let Ok(line) = line else { break };
+                if to_stderr {
+                    eprintln!("{}", line);
+                } else {
+                    println!("{}", line);
+                    let _ = std::io::stdout().flush();
+                }
+                if re.is_match(&line) {
+                    hit.store(true, Ordering::SeqCst);
+                }
+            }
+            closed.store(true, Ordering::SeqCst);
+        });
+    }
+
+    pub fn reached(&mut self) -> bool {
+        match (&self.until, &mut self.state) {
+            (
+                Until::ProcessAppears(f) | Until::ProcessExits(f),
+                State::Procs { sys, before, seen },
+            ) => {
+                sys.refresh_processes_specifics(refresh_kind());
+                let running = sys
+                    .processes()
+                    .iter()
+                    .filter(|(pid, p)| {
+                        !before.contains(pid)
+                            && p.thread_kind() != Some(ThreadKind::Userland)
+                            && p.status() != ProcessStatus::Zombie
+                            && f.matches_name(p)
+                    })
+                    .count();
+                if matches!(self.until, Until::ProcessAppears(_)) {
+                    running > 0
+                } else {
+                    *seen |= running > 0;
+                    *seen && running == 0
+                }
+            }
+            (_, State::Stdout { hit, .. }) => hit.load(Ordering::SeqCst),
+            (Until::File(path), State::File(before)) => {
+                modified(path).is_some_and(|now| Some(now) != *before)
+            }
+            (Until::Port(port), State::Port) => accepts(*port),
+            _ => false,
+        }
+    }
+}